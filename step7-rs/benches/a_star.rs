@@ -0,0 +1,71 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rustplan::a_star::{SearchLimits, SearchMode};
+use rustplan::actions::DoorState;
+use rustplan::schema::Domain;
+use rustplan::{
+    run_scenario, Actor, Algorithm, Coordination, Heuristic, Objective, Scenario, State, World,
+};
+
+// A corridor of `rooms` rooms with `room_size` positions each, joined end to
+// end by closed doors. The actor starts in the first room and has to reach
+// the far end, closing every door behind it.
+fn corridor(rooms: usize, room_size: usize) -> Scenario {
+    let pos_count = rooms * room_size;
+    let doors = rooms - 1;
+
+    let world = World {
+        pos_move_groups: (0..pos_count).map(|pos| pos / room_size).collect(),
+        door_side_a: (0..doors).map(|door| (door + 1) * room_size - 1).collect(),
+        door_side_b: (0..doors).map(|door| (door + 1) * room_size).collect(),
+        key_pos: vec![],
+        key_door: vec![],
+        break_door_cost: 5.0,
+        activatable_pos: vec![],
+        activatable_fills: vec![],
+    };
+
+    let state = State {
+        actors: vec![Actor {
+            pos: 0,
+            inventory: vec![],
+            holding: None,
+        }],
+        door_states: vec![DoorState::Closed; doors],
+        holdable_pos: vec![],
+        holdable_filled: vec![],
+        activated: vec![],
+        flags: vec![],
+    };
+
+    let mut objectives = vec![Objective::ActorPos(0, pos_count - 1)];
+    objectives.extend((0..doors).map(|door| Objective::DoorState(door, DoorState::Closed)));
+
+    Scenario {
+        world,
+        state,
+        objectives,
+        algorithm: Algorithm::AStar,
+        mode: SearchMode::AStar,
+        coordination: Coordination::Sequential,
+        heuristic: Heuristic::GoalCount,
+        limits: SearchLimits::default(),
+        include_states: false,
+        domain: Domain::default(),
+    }
+}
+
+fn bench_corridor(c: &mut Criterion) {
+    let mut group = c.benchmark_group("corridor");
+    group.sample_size(10);
+
+    for rooms in [4, 8, 12] {
+        group.bench_with_input(BenchmarkId::from_parameter(rooms), &rooms, |b, &rooms| {
+            b.iter(|| run_scenario(corridor(rooms, 6)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_corridor);
+criterion_main!(benches);
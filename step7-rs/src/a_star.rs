@@ -1,12 +1,73 @@
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-type Cost = f32;
+pub type Cost = f32;
+
+/// Why a search did not produce a plan
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "code", content = "detail", rename_all = "snake_case")]
+pub enum PlanError {
+    /// Every reachable state was expanded without reaching the goal
+    Unreachable,
+    /// The search gave up after using up one of its limits
+    BudgetExceeded(Budget),
+    /// The search was cancelled through its CancelToken
+    Cancelled,
+    /// The problem handed to the planner is malformed
+    InvalidScenario(String),
+    /// The heuristic returned NaN for some state
+    HeuristicNan,
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::Unreachable => write!(f, "goal is unreachable"),
+            PlanError::BudgetExceeded(budget) => write!(f, "search {} budget exceeded", budget),
+            PlanError::Cancelled => write!(f, "search cancelled"),
+            PlanError::InvalidScenario(reason) => write!(f, "invalid scenario: {}", reason),
+            PlanError::HeuristicNan => write!(f, "heuristic produced NaN"),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// The search limit that ran out
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Budget {
+    Expansions,
+    Generated,
+    States,
+    Time,
+    /// Too many actions from grounding the action schemas
+    GroundActions,
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Budget::Expansions => "expansion",
+            Budget::Generated => "generated node",
+            Budget::States => "state",
+            Budget::Time => "time",
+            Budget::GroundActions => "ground action",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 #[derive(Debug)]
 pub struct Neighbor<S, A> {
-    state: S,
-    cost: Cost,
-    action: A,
+    pub(crate) state: S,
+    pub(crate) cost: Cost,
+    pub(crate) action: A,
 }
 
 impl<S, A> Neighbor<S, A> {
@@ -19,79 +80,703 @@ impl<S, A> Neighbor<S, A> {
     }
 }
 
-pub fn a_star<S, A>(
+/// One action of a plan, with the state it leads to
+#[derive(Debug, Clone)]
+pub struct Step<S, A> {
+    pub action: A,
+    /// Cost of this action alone
+    pub cost: Cost,
+    /// Cost of the plan up to and including this action
+    pub g: Cost,
+    pub state: S,
+}
+
+// A state discovered by the search, stored once and referred to by index
+pub(crate) struct Node<S, A> {
+    pub(crate) state: S,
+    pub(crate) g: Cost,
+    pub(crate) h: Cost,
+    pub(crate) parent: Option<(usize, A, Cost)>,
+    pub(crate) closed: bool,
+}
+
+// Entry in the open set. Entries are never updated in place; a better path
+// to a node pushes a new entry and the old one is skipped when popped.
+pub(crate) struct OpenEntry {
+    pub(crate) f: Cost,
+    pub(crate) g: Cost,
+    pub(crate) seq: usize,
+    pub(crate) node: usize,
+}
+
+impl PartialEq for OpenEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenEntry {}
+
+impl PartialOrd for OpenEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenEntry {
+    // BinaryHeap is a max-heap, so the lowest f compares greatest. Ties are
+    // broken first-in first-out.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .f
+            .partial_cmp(&self.f)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// How a search weighs the cost so far against the heuristic
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum SearchMode {
+    /// Optimal search, f = g + h
+    #[default]
+    AStar,
+    /// f = g + w * h, trading optimality for speed when w > 1
+    Weighted(f32),
+    /// Greedy best-first, f = h
+    Greedy,
+    /// Dijkstra, f = g with the heuristic ignored
+    UniformCost,
+}
+
+impl SearchMode {
+    fn priority(&self, g: Cost, h: Cost) -> Cost {
+        match self {
+            SearchMode::AStar => g + h,
+            SearchMode::Weighted(w) => g + w * h,
+            SearchMode::Greedy => h,
+            SearchMode::UniformCost => g,
+        }
+    }
+
+    /// Factor by which a plan found in this mode can exceed the optimal cost,
+    /// given an admissible heuristic. `None` means there is no bound.
+    pub fn suboptimality_bound(&self) -> Option<f32> {
+        match self {
+            SearchMode::AStar | SearchMode::UniformCost => Some(1.0),
+            SearchMode::Weighted(w) => Some(w.max(1.0)),
+            SearchMode::Greedy => None,
+        }
+    }
+}
+
+/// Shared flag a caller can trip to stop a running search
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/// How far a running search has got
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Progress {
+    #[serde(flatten)]
+    pub stats: SearchStats,
+    /// The priority of the state being expanded, the lowest left to
+    /// expand. In A* with an admissible heuristic, a lower bound on the
+    /// plan's cost.
+    pub best_f: Option<Cost>,
+    /// States waiting to be expanded
+    pub open_size: usize,
+}
+
+/// Somewhere a running search reports its progress every few expansions
+pub trait ReportProgress<S, A> {
+    /// `partial` gives the path to the state that looks closest to the
+    /// goal, for reporters that want it
+    fn report(&self, progress: Progress, partial: impl FnOnce() -> Option<Vec<Step<S, A>>>);
+}
+
+/// Shared view of a running search's progress, which the search updates
+/// every few expansions. Partial paths are not kept.
+#[derive(Debug, Clone, Default)]
+pub struct ProgressHandle(Arc<Mutex<Progress>>);
+
+// Expansions between updates of a ProgressHandle
+const PROGRESS_INTERVAL: usize = 128;
+
+// Neighbors generated between checks of the limits while a single state
+// is expanded
+const GENERATED_CHECK_INTERVAL: usize = 256;
+
+impl ProgressHandle {
+    pub fn new() -> ProgressHandle {
+        ProgressHandle::default()
+    }
+
+    /// The progress as of the search's last update
+    pub fn latest(&self) -> Progress {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+impl<S, A> ReportProgress<S, A> for ProgressHandle {
+    fn report(&self, progress: Progress, _: impl FnOnce() -> Option<Vec<Step<S, A>>>) {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = progress;
+    }
+}
+
+/// Points at which a search gives up. Limits left as `None` are unbounded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, bound(deserialize = ""))]
+pub struct SearchLimits<P = ProgressHandle> {
+    /// Maximum number of states expanded
+    pub max_expansions: Option<usize>,
+    /// Maximum number of neighbors generated
+    pub max_generated: Option<usize>,
+    /// Maximum number of distinct states held in the open and closed sets
+    pub max_states: Option<usize>,
+    /// Maximum wall-clock time in milliseconds
+    pub timeout_ms: Option<u64>,
+    #[serde(skip)]
+    pub cancel: Option<CancelToken>,
+    /// Where the search reports its progress while it runs
+    #[serde(skip)]
+    pub progress: Option<P>,
+}
+
+// Derived, this would need the progress reporter to have a default too
+impl<P> Default for SearchLimits<P> {
+    fn default() -> SearchLimits<P> {
+        SearchLimits {
+            max_expansions: None,
+            max_generated: None,
+            max_states: None,
+            timeout_ms: None,
+            cancel: None,
+            progress: None,
+        }
+    }
+}
+
+impl<P: Clone> SearchLimits<P> {
+    /// Combine two sets of limits, keeping the stricter of each
+    pub fn tightest(&self, other: &SearchLimits<P>) -> SearchLimits<P> {
+        fn min<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        SearchLimits {
+            max_expansions: min(self.max_expansions, other.max_expansions),
+            max_generated: min(self.max_generated, other.max_generated),
+            max_states: min(self.max_states, other.max_states),
+            timeout_ms: min(self.timeout_ms, other.timeout_ms),
+            cancel: self.cancel.clone().or_else(|| other.cancel.clone()),
+            progress: self.progress.clone().or_else(|| other.progress.clone()),
+        }
+    }
+
+    /// What is left of the limits after some of them have been used, e.g.
+    /// by earlier searches of the same plan
+    pub fn remaining(&self, used: &SearchStats) -> SearchLimits<P> {
+        let left = |limit: Option<usize>, used: usize| limit.map(|m| m.saturating_sub(used));
+
+        SearchLimits {
+            max_expansions: left(self.max_expansions, used.expansions),
+            max_generated: left(self.max_generated, used.generated),
+            max_states: self.max_states,
+            timeout_ms: self
+                .timeout_ms
+                .map(|t| t.saturating_sub(used.elapsed_ms as u64)),
+            cancel: self.cancel.clone(),
+            progress: self.progress.clone(),
+        }
+    }
+
+    /// The same limits, reporting progress somewhere else
+    pub fn with_progress<Q>(&self, progress: Option<Q>) -> SearchLimits<Q> {
+        SearchLimits {
+            max_expansions: self.max_expansions,
+            max_generated: self.max_generated,
+            max_states: self.max_states,
+            timeout_ms: self.timeout_ms,
+            cancel: self.cancel.clone(),
+            progress,
+        }
+    }
+}
+
+impl<P> SearchLimits<P> {
+    // Report progress, if there is somewhere to report it and an update is
+    // due. Searches call this before each expansion.
+    pub(crate) fn report<S, A>(
+        &self,
+        started: Instant,
+        stats: &SearchStats,
+        best_f: Cost,
+        open_size: usize,
+        partial: impl FnOnce() -> Option<Vec<Step<S, A>>>,
+    ) where
+        P: ReportProgress<S, A>,
+    {
+        let progress = match &self.progress {
+            Some(progress) if stats.expansions.is_multiple_of(PROGRESS_INTERVAL) => progress,
+            _ => return,
+        };
+
+        let mut stats = stats.clone();
+        stats.elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let latest = Progress {
+            stats,
+            best_f: Some(best_f),
+            open_size,
+        };
+        progress.report(latest, partial);
+    }
+
+    // Like `exceeded`, but only checked every few neighbors, for use while
+    // a state's neighbors are added. A state can have very many, such as
+    // the joint steps of a large squad.
+    pub(crate) fn exceeded_while_expanding(
+        &self,
+        started: Instant,
+        stats: &SearchStats,
+        states: usize,
+    ) -> Option<PlanError> {
+        if !stats.generated.is_multiple_of(GENERATED_CHECK_INTERVAL) {
+            return None;
+        }
+
+        self.exceeded(started, stats, states)
+    }
+
+    // The first limit a search has run into, if any
+    pub(crate) fn exceeded(
+        &self,
+        started: Instant,
+        stats: &SearchStats,
+        states: usize,
+    ) -> Option<PlanError> {
+        let timeout = self.timeout_ms.map(Duration::from_millis);
+
+        if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+            Some(PlanError::Cancelled)
+        } else if timeout.is_some_and(|t| started.elapsed() >= t) {
+            Some(PlanError::BudgetExceeded(Budget::Time))
+        } else if self.max_expansions.is_some_and(|m| stats.expansions >= m) {
+            Some(PlanError::BudgetExceeded(Budget::Expansions))
+        } else if self.max_generated.is_some_and(|m| stats.generated >= m) {
+            Some(PlanError::BudgetExceeded(Budget::Generated))
+        } else if self.max_states.is_some_and(|m| states >= m) {
+            Some(PlanError::BudgetExceeded(Budget::States))
+        } else {
+            None
+        }
+    }
+}
+
+/// Counters describing how much work a search did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SearchStats {
+    /// States taken off the open set and expanded
+    pub expansions: usize,
+    /// Neighbors produced by expanding states
+    pub generated: usize,
+    /// Neighbors discarded because their state was already reached as cheaply
+    pub duplicates_pruned: usize,
+    /// Largest size the open set reached
+    pub max_open_size: usize,
+    /// Cost of the path to the goal, if one was found
+    pub final_g: Option<Cost>,
+    /// Wall-clock time the search took, in milliseconds
+    pub elapsed_ms: f64,
+}
+
+pub struct SearchOutcome<S, A> {
+    /// The path to a goal state, or why none was found
+    pub result: Result<Vec<Step<S, A>>, PlanError>,
+    /// When a limit stopped the search, the path to the expanded state that
+    /// looked closest to the goal
+    pub partial: Option<Vec<Step<S, A>>>,
+    pub stats: SearchStats,
+}
+
+pub fn a_star<S, A, P>(
     start: &S,
+    is_goal: &dyn Fn(&S) -> bool,
     heuristic: &dyn Fn(&S) -> f32,
     neighbors: &dyn Fn(&S) -> Vec<Neighbor<S, A>>,
-) -> Result<Vec<(S, A)>, ()>
+    mode: SearchMode,
+    limits: &SearchLimits<P>,
+) -> SearchOutcome<S, A>
 where
     S: Clone + PartialEq + Eq + std::hash::Hash + std::fmt::Debug,
     A: Clone,
+    P: ReportProgress<S, A>,
 {
-    let mut open_set: Vec<S> = vec![start.clone()];
-
-    let mut came_from = HashMap::new();
-
-    // Cost of path from start to node
-    let mut g_score = HashMap::new();
-    g_score.insert(start.clone(), 0.0);
-
-    // Estimated cost of path from start through node to goal
-    // This is an estimate of the total path cost.
-    let mut f_score = HashMap::new();
-    f_score.insert(start.clone(), heuristic(start));
-
-    while !open_set.is_empty() {
-        open_set.sort_by(|a, b| {
-            f_score
-                .get(a)
-                .unwrap_or(&f32::INFINITY)
-                .partial_cmp(f_score.get(b).unwrap_or(&f32::INFINITY))
-                .unwrap()
+    let started = Instant::now();
+    let mut stats = SearchStats::default();
+
+    let outcome = |result, partial, mut stats: SearchStats| {
+        stats.elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        SearchOutcome {
+            result,
+            partial,
+            stats,
+        }
+    };
+
+    let heuristic = |state: &S| match heuristic(state) {
+        h if h.is_nan() => Err(PlanError::HeuristicNan),
+        h => Ok(h),
+    };
+
+    let start_h = match heuristic(start) {
+        Ok(h) => h,
+        Err(error) => return outcome(Err(error), None, stats),
+    };
+
+    let mut nodes: Vec<Node<S, A>> = vec![Node {
+        state: start.clone(),
+        g: 0.0,
+        h: start_h,
+        parent: None,
+        closed: false,
+    }];
+
+    let mut index = HashMap::new();
+    index.insert(start.clone(), 0);
+
+    let mut seq = 0;
+    let mut open_set = BinaryHeap::new();
+    open_set.push(OpenEntry {
+        f: mode.priority(0.0, start_h),
+        g: 0.0,
+        seq,
+        node: 0,
+    });
+    stats.max_open_size = 1;
+
+    // Expanded node with the lowest heuristic, returned as a partial plan
+    // if the search is cut short
+    let mut best = 0;
+
+    while let Some(entry) = open_set.pop() {
+        let current = entry.node;
+
+        // Stale entry for a node that has since been reached more cheaply
+        if nodes[current].closed || entry.g > nodes[current].g {
+            continue;
+        }
+
+        if is_goal(&nodes[current].state) {
+            stats.final_g = Some(nodes[current].g);
+            return outcome(Ok(reconstruct_path(&nodes, current)), None, stats);
+        }
+
+        if let Some(error) = limits.exceeded(started, &stats, nodes.len()) {
+            return outcome(Err(error), Some(reconstruct_path(&nodes, best)), stats);
+        }
+        limits.report(started, &stats, entry.f, open_set.len(), || {
+            Some(reconstruct_path(&nodes, best))
         });
 
-        let current = &open_set.remove(0);
+        nodes[current].closed = true;
+        stats.expansions += 1;
 
-        if heuristic(current) == 0.0 {
-            return Ok(reconstruct_path(came_from, current));
+        let (best_h, best_g) = (nodes[best].h, nodes[best].g);
+        if (nodes[current].h, nodes[current].g) < (best_h, best_g) {
+            best = current;
         }
 
-        for neighbor in neighbors(current) {
-            let tentative_g_score = g_score.get(current).unwrap_or(&f32::INFINITY) + neighbor.cost;
-            if tentative_g_score < *g_score.get(&neighbor.state).unwrap_or(&f32::INFINITY) {
-                // This path to the neighbor is the best one seen so far
-                came_from.insert(
-                    neighbor.state.clone(),
-                    (current.clone(), neighbor.action.to_owned()),
-                );
-                g_score.insert(neighbor.state.clone(), tentative_g_score);
-                f_score.insert(
-                    neighbor.state.clone(),
-                    tentative_g_score + heuristic(&neighbor.state),
-                );
+        let current_g = nodes[current].g;
+
+        for neighbor in neighbors(&nodes[current].state) {
+            stats.generated += 1;
+            if let Some(error) = limits.exceeded_while_expanding(started, &stats, nodes.len()) {
+                return outcome(Err(error), Some(reconstruct_path(&nodes, best)), stats);
+            }
+
+            let tentative_g_score = current_g + neighbor.cost;
 
-                if !open_set.contains(&neighbor.state) {
-                    open_set.push(neighbor.state.clone())
+            let next = match index.get(&neighbor.state) {
+                Some(&i) => {
+                    if tentative_g_score >= nodes[i].g {
+                        stats.duplicates_pruned += 1;
+                        continue;
+                    }
+
+                    // This path to the neighbor is the best one seen so far,
+                    // so (re)open it.
+                    nodes[i].g = tentative_g_score;
+                    nodes[i].parent = Some((current, neighbor.action, neighbor.cost));
+                    nodes[i].closed = false;
+                    i
+                }
+                None => {
+                    let h = match heuristic(&neighbor.state) {
+                        Ok(h) => h,
+                        Err(error) => return outcome(Err(error), None, stats),
+                    };
+
+                    let i = nodes.len();
+                    index.insert(neighbor.state.clone(), i);
+                    nodes.push(Node {
+                        state: neighbor.state,
+                        g: tentative_g_score,
+                        h,
+                        parent: Some((current, neighbor.action, neighbor.cost)),
+                        closed: false,
+                    });
+                    i
                 }
+            };
+
+            // The heuristic proved the goal unreachable from here
+            if nodes[next].h == f32::INFINITY {
+                continue;
             }
+
+            seq += 1;
+            open_set.push(OpenEntry {
+                f: mode.priority(tentative_g_score, nodes[next].h),
+                g: tentative_g_score,
+                seq,
+                node: next,
+            });
         }
+
+        stats.max_open_size = stats.max_open_size.max(open_set.len());
     }
 
-    Err(())
+    outcome(Err(PlanError::Unreachable), None, stats)
 }
 
-fn reconstruct_path<S, A>(came_from: HashMap<S, (S, A)>, current: &S) -> Vec<(S, A)>
+pub(crate) fn reconstruct_path<S, A>(nodes: &[Node<S, A>], current: usize) -> Vec<Step<S, A>>
 where
-    S: Clone + Eq + std::hash::Hash,
+    S: Clone,
     A: Clone,
 {
-    let mut total_path: Vec<(S, A)> = vec![];
+    let mut total_path: Vec<Step<S, A>> = vec![];
     let mut current = current;
 
-    while let Some((state, action)) = came_from.get(current) {
-        total_path.insert(0, (state.clone(), action.to_owned()));
-        current = state;
+    while let Some((parent, action, cost)) = &nodes[current].parent {
+        total_path.push(Step {
+            action: action.clone(),
+            cost: *cost,
+            g: nodes[current].g,
+            state: nodes[current].state.clone(),
+        });
+        current = *parent;
     }
 
+    total_path.reverse();
     total_path
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Keeps the last state of every partial path it is given
+    struct Recorder<'a>(RefCell<Vec<&'a str>>);
+
+    impl<'a> ReportProgress<&'a str, usize> for Recorder<'a> {
+        fn report(&self, _: Progress, partial: impl FnOnce() -> Option<Vec<Step<&'a str, usize>>>) {
+            let last = partial().and_then(|path| path.last().map(|step| step.state));
+            self.0.borrow_mut().extend(last);
+        }
+    }
+
+    #[test]
+    fn searches_borrowed_states_and_reports_typed_partial_paths() {
+        // A line of 300 places, searched from one end to the other
+        let names: Vec<String> = (0..300).map(|i| format!("place {}", i)).collect();
+        let place = |name: &str| names.iter().position(|n| n == name).unwrap();
+
+        let limits = SearchLimits {
+            progress: Some(Recorder(RefCell::new(vec![]))),
+            ..SearchLimits::default()
+        };
+        let outcome = a_star(
+            &names[0].as_str(),
+            &|s| place(s) == names.len() - 1,
+            &|s| (names.len() - 1 - place(s)) as f32,
+            &|s| {
+                let next = names.get(place(s) + 1);
+                next.map(|n| Neighbor::new(n.as_str(), 1.0, 1))
+                    .into_iter()
+                    .collect()
+            },
+            SearchMode::AStar,
+            &limits,
+        );
+
+        let path = outcome.result.unwrap();
+        assert_eq!(path.len(), names.len() - 1);
+
+        let reported = limits.progress.unwrap().0.into_inner();
+        assert_eq!(reported, ["place 127", "place 255"]);
+    }
+
+    // Numbers, each leading to the next one and to its double. There is
+    // no goal, so only a limit stops the search.
+    fn endless(limits: &SearchLimits) -> SearchOutcome<u64, ()> {
+        a_star(
+            &1,
+            &|_| false,
+            &|_| 1.0,
+            &|&n| vec![Neighbor::new(n + 1, 1.0, ()), Neighbor::new(n * 2, 1.0, ())],
+            SearchMode::AStar,
+            limits,
+        )
+    }
+
+    #[test]
+    fn count_limits_stop_the_search() {
+        let limits = SearchLimits {
+            max_expansions: Some(50),
+            ..SearchLimits::default()
+        };
+        let outcome = endless(&limits);
+        assert_eq!(
+            outcome.result.unwrap_err(),
+            PlanError::BudgetExceeded(Budget::Expansions)
+        );
+        assert_eq!(outcome.stats.expansions, 50);
+        assert!(outcome.partial.is_some());
+
+        let limits = SearchLimits {
+            max_generated: Some(1000),
+            ..SearchLimits::default()
+        };
+        let outcome = endless(&limits);
+        assert_eq!(
+            outcome.result.unwrap_err(),
+            PlanError::BudgetExceeded(Budget::Generated)
+        );
+
+        let limits = SearchLimits {
+            max_states: Some(100),
+            ..SearchLimits::default()
+        };
+        let outcome = endless(&limits);
+        assert_eq!(
+            outcome.result.unwrap_err(),
+            PlanError::BudgetExceeded(Budget::States)
+        );
+    }
+
+    #[test]
+    fn the_time_limit_stops_the_search() {
+        let limits = SearchLimits {
+            timeout_ms: Some(20),
+            ..SearchLimits::default()
+        };
+        let outcome = endless(&limits);
+        assert_eq!(
+            outcome.result.unwrap_err(),
+            PlanError::BudgetExceeded(Budget::Time)
+        );
+        assert!(outcome.stats.elapsed_ms >= 20.0);
+    }
+
+    #[test]
+    fn cancelling_stops_the_search() {
+        let cancel = CancelToken::new();
+        let limits = SearchLimits {
+            cancel: Some(cancel.clone()),
+            ..SearchLimits::default()
+        };
+
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        let outcome = endless(&limits);
+        canceller.join().unwrap();
+
+        assert_eq!(outcome.result.unwrap_err(), PlanError::Cancelled);
+        assert!(outcome.stats.expansions > 0);
+    }
+
+    type Cell = (i32, i32);
+
+    const SIZE: i32 = 10;
+
+    // A grid with a patch of mud in the middle that is slow to cross. The
+    // manhattan distance to the far corner never overestimates.
+    fn grid(mode: SearchMode) -> Vec<Step<Cell, ()>> {
+        let limits: SearchLimits = SearchLimits::default();
+        let outcome = a_star(
+            &(0, 0),
+            &|&cell| cell == (SIZE - 1, SIZE - 1),
+            &|&(x, y)| ((SIZE - 1 - x) + (SIZE - 1 - y)) as f32,
+            &|&(x, y)| {
+                [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .iter()
+                    .map(|(dx, dy)| (x + dx, y + dy))
+                    .filter(|&(x, y)| (0..SIZE).contains(&x) && (0..SIZE).contains(&y))
+                    .map(|(x, y)| {
+                        let mud = (1..SIZE - 1).contains(&x) && (3..SIZE).contains(&y);
+                        Neighbor::new((x, y), if mud { 3.0 } else { 1.0 }, ())
+                    })
+                    .collect()
+            },
+            mode,
+            &limits,
+        );
+        outcome.result.unwrap()
+    }
+
+    fn cost(path: &[Step<Cell, ()>]) -> Cost {
+        path.last().unwrap().g
+    }
+
+    #[test]
+    fn uniform_cost_finds_the_optimal_plan() {
+        let optimal = cost(&grid(SearchMode::AStar));
+        assert_eq!(cost(&grid(SearchMode::UniformCost)), optimal);
+    }
+
+    #[test]
+    fn weighted_plans_stay_within_their_weight() {
+        let optimal = cost(&grid(SearchMode::AStar));
+
+        for w in [1.5, 2.0, 5.0] {
+            let weighted = cost(&grid(SearchMode::Weighted(w)));
+            assert!(weighted >= optimal);
+            assert!(weighted <= w * optimal, "{} with weight {}", weighted, w);
+        }
+    }
+
+    #[test]
+    fn greedy_finds_a_plan() {
+        let path = grid(SearchMode::Greedy);
+        assert_eq!(path.last().unwrap().state, (SIZE - 1, SIZE - 1));
+        assert_eq!(cost(&path), path.iter().map(|step| step.cost).sum::<Cost>());
+        assert_eq!(SearchMode::Greedy.suboptimality_bound(), None);
+    }
+}
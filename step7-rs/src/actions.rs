@@ -1,16 +1,115 @@
-use crate::{DoorId, PosId};
-use crate::{Neighbor, State, World};
+use crate::a_star::Neighbor;
+use crate::{ActivatableId, ActorId, DoorId, HoldableId, KeyId, PosId};
+use crate::{State, World};
 use serde::{Deserialize, Serialize};
 
+/// An action of one actor. Each carries the id of the actor doing it.
 #[derive(Debug, Clone, Serialize)]
 pub enum Action {
-    Move { to: PosId },
-    OpenDoor { door: DoorId },
-    CloseDoor { door: DoorId },
-    TraverseDoor { door: DoorId, to: PosId },
+    Move {
+        actor: ActorId,
+        to: PosId,
+    },
+    OpenDoor {
+        actor: ActorId,
+        door: DoorId,
+    },
+    CloseDoor {
+        actor: ActorId,
+        door: DoorId,
+    },
+    TraverseDoor {
+        actor: ActorId,
+        door: DoorId,
+        to: PosId,
+    },
+    PickUp {
+        actor: ActorId,
+        key: KeyId,
+    },
+    UnlockDoor {
+        actor: ActorId,
+        door: DoorId,
+        key: KeyId,
+    },
+    LockDoor {
+        actor: ActorId,
+        door: DoorId,
+        key: KeyId,
+    },
+    BreakDoor {
+        actor: ActorId,
+        door: DoorId,
+    },
+    Take {
+        actor: ActorId,
+        holdable: HoldableId,
+    },
+    PutDown {
+        actor: ActorId,
+        holdable: HoldableId,
+    },
+    TurnOn {
+        actor: ActorId,
+        activatable: ActivatableId,
+    },
+    TurnOff {
+        actor: ActorId,
+        activatable: ActivatableId,
+    },
+    Wait {
+        actor: ActorId,
+    },
+    /// A ground action from one of the scenario's action schemas
+    Schema {
+        actor: ActorId,
+        name: String,
+        args: Vec<usize>,
+    },
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
+impl Action {
+    /// The door the action operates on, if any
+    pub fn door(&self) -> Option<DoorId> {
+        match self {
+            Action::Move { .. }
+            | Action::PickUp { .. }
+            | Action::Take { .. }
+            | Action::PutDown { .. }
+            | Action::TurnOn { .. }
+            | Action::TurnOff { .. }
+            | Action::Wait { .. }
+            | Action::Schema { .. } => None,
+            Action::OpenDoor { door, .. }
+            | Action::CloseDoor { door, .. }
+            | Action::TraverseDoor { door, .. }
+            | Action::UnlockDoor { door, .. }
+            | Action::LockDoor { door, .. }
+            | Action::BreakDoor { door, .. } => Some(*door),
+        }
+    }
+
+    pub fn actor(&self) -> ActorId {
+        match self {
+            Action::Move { actor, .. }
+            | Action::OpenDoor { actor, .. }
+            | Action::CloseDoor { actor, .. }
+            | Action::TraverseDoor { actor, .. }
+            | Action::PickUp { actor, .. }
+            | Action::UnlockDoor { actor, .. }
+            | Action::LockDoor { actor, .. }
+            | Action::BreakDoor { actor, .. }
+            | Action::Take { actor, .. }
+            | Action::PutDown { actor, .. }
+            | Action::TurnOn { actor, .. }
+            | Action::TurnOff { actor, .. }
+            | Action::Wait { actor }
+            | Action::Schema { actor, .. } => *actor,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum DoorState {
     Open,
     Closed,
@@ -18,22 +117,32 @@ pub enum DoorState {
     Broken,
 }
 
-pub fn move_actor(state: &State, world: &World, to: PosId) -> Option<Neighbor<State, Action>> {
-    let precondition = (state.actor_pos != to)
-        && (world.pos_move_groups[state.actor_pos] == world.pos_move_groups[to]);
+pub fn move_actor(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    to: PosId,
+) -> Option<Neighbor<State, Action>> {
+    let precondition = (state.actors[actor].pos != to)
+        && (world.pos_move_groups[state.actors[actor].pos] == world.pos_move_groups[to]);
 
     if !precondition {
         return None;
     }
 
     let mut new_state = state.clone();
-    new_state.actor_pos = to;
+    new_state.move_actor_to(actor, to);
 
-    Some(Neighbor::new(new_state, 1.0, Action::Move { to }))
+    Some(Neighbor::new(new_state, 1.0, Action::Move { actor, to }))
 }
 
-pub fn open_door(state: &State, world: &World, door: DoorId) -> Option<Neighbor<State, Action>> {
-    let actor_pos = state.actor_pos;
+pub fn open_door(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    door: DoorId,
+) -> Option<Neighbor<State, Action>> {
+    let actor_pos = state.actors[actor].pos;
     let door_side_a = world.door_side_a[door];
     let door_side_b = world.door_side_b[door];
 
@@ -47,11 +156,20 @@ pub fn open_door(state: &State, world: &World, door: DoorId) -> Option<Neighbor<
     let mut new_state = state.clone();
     new_state.door_states[door] = DoorState::Open;
 
-    Some(Neighbor::new(new_state, 1.0, Action::OpenDoor { door }))
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::OpenDoor { actor, door },
+    ))
 }
 
-pub fn close_door(state: &State, world: &World, door: DoorId) -> Option<Neighbor<State, Action>> {
-    let actor_pos = state.actor_pos;
+pub fn close_door(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    door: DoorId,
+) -> Option<Neighbor<State, Action>> {
+    let actor_pos = state.actors[actor].pos;
     let door_side_a = world.door_side_a[door];
     let door_side_b = world.door_side_b[door];
 
@@ -65,20 +183,25 @@ pub fn close_door(state: &State, world: &World, door: DoorId) -> Option<Neighbor
     let mut new_state = state.clone();
     new_state.door_states[door] = DoorState::Closed;
 
-    Some(Neighbor::new(new_state, 1.0, Action::CloseDoor { door }))
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::CloseDoor { actor, door },
+    ))
 }
 
 pub fn traverse_door(
     state: &State,
     world: &World,
+    actor: ActorId,
     door: DoorId,
 ) -> Option<Neighbor<State, Action>> {
-    let actor_pos = state.actor_pos;
+    let actor_pos = state.actors[actor].pos;
     let door_side_a = world.door_side_a[door];
     let door_side_b = world.door_side_b[door];
 
     let precondition = (actor_pos == door_side_a || actor_pos == door_side_b)
-        && state.door_states[door] == DoorState::Open;
+        && matches!(state.door_states[door], DoorState::Open | DoorState::Broken);
 
     if !precondition {
         return None;
@@ -91,6 +214,387 @@ pub fn traverse_door(
     };
 
     let mut new_state = state.clone();
-    new_state.actor_pos = new_pos;
-    Some(Neighbor::new(new_state, 1.0, Action::TraverseDoor { door, to: new_pos }))
+    new_state.move_actor_to(actor, new_pos);
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::TraverseDoor {
+            actor,
+            door,
+            to: new_pos,
+        },
+    ))
+}
+
+pub fn pick_up(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    key: KeyId,
+) -> Option<Neighbor<State, Action>> {
+    let precondition = state.actors[actor].pos == world.key_pos[key] && !state.held(key);
+
+    if !precondition {
+        return None;
+    }
+
+    let mut new_state = state.clone();
+    new_state.actors[actor].pick_up(key);
+
+    Some(Neighbor::new(new_state, 1.0, Action::PickUp { actor, key }))
+}
+
+// The first key the actor holds that fits the door
+fn held_key(state: &State, world: &World, actor: ActorId, door: DoorId) -> Option<KeyId> {
+    state.actors[actor]
+        .inventory
+        .iter()
+        .copied()
+        .find(|&key| world.key_door[key] == door)
+}
+
+pub fn unlock_door(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    door: DoorId,
+) -> Option<Neighbor<State, Action>> {
+    let actor_pos = state.actors[actor].pos;
+    let door_side_a = world.door_side_a[door];
+    let door_side_b = world.door_side_b[door];
+
+    let precondition = (actor_pos == door_side_a || actor_pos == door_side_b)
+        && state.door_states[door] == DoorState::Locked;
+
+    if !precondition {
+        return None;
+    }
+
+    let key = held_key(state, world, actor, door)?;
+
+    let mut new_state = state.clone();
+    new_state.door_states[door] = DoorState::Closed;
+
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::UnlockDoor { actor, door, key },
+    ))
+}
+
+pub fn lock_door(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    door: DoorId,
+) -> Option<Neighbor<State, Action>> {
+    let actor_pos = state.actors[actor].pos;
+    let door_side_a = world.door_side_a[door];
+    let door_side_b = world.door_side_b[door];
+
+    let precondition = (actor_pos == door_side_a || actor_pos == door_side_b)
+        && state.door_states[door] == DoorState::Closed;
+
+    if !precondition {
+        return None;
+    }
+
+    let key = held_key(state, world, actor, door)?;
+
+    let mut new_state = state.clone();
+    new_state.door_states[door] = DoorState::Locked;
+
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::LockDoor { actor, door, key },
+    ))
+}
+
+pub fn break_door(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    door: DoorId,
+) -> Option<Neighbor<State, Action>> {
+    let actor_pos = state.actors[actor].pos;
+    let door_side_a = world.door_side_a[door];
+    let door_side_b = world.door_side_b[door];
+
+    let precondition = (actor_pos == door_side_a || actor_pos == door_side_b)
+        && matches!(
+            state.door_states[door],
+            DoorState::Closed | DoorState::Locked
+        );
+
+    if !precondition {
+        return None;
+    }
+
+    let mut new_state = state.clone();
+    new_state.door_states[door] = DoorState::Broken;
+
+    Some(Neighbor::new(
+        new_state,
+        world.break_door_cost,
+        Action::BreakDoor { actor, door },
+    ))
+}
+
+pub fn take(
+    state: &State,
+    actor: ActorId,
+    holdable: HoldableId,
+) -> Option<Neighbor<State, Action>> {
+    let precondition = state.actors[actor].holding.is_none()
+        && state.holdable_pos[holdable] == state.actors[actor].pos
+        && !state.in_hands(holdable);
+
+    if !precondition {
+        return None;
+    }
+
+    let mut new_state = state.clone();
+    new_state.actors[actor].holding = Some(holdable);
+
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::Take { actor, holdable },
+    ))
+}
+
+pub fn put_down(state: &State, actor: ActorId) -> Option<Neighbor<State, Action>> {
+    let holdable = state.actors[actor].holding?;
+
+    let mut new_state = state.clone();
+    new_state.actors[actor].holding = None;
+
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::PutDown { actor, holdable },
+    ))
+}
+
+pub fn turn_on(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    activatable: ActivatableId,
+) -> Option<Neighbor<State, Action>> {
+    let precondition = state.actors[actor].pos == world.activatable_pos[activatable]
+        && !state.activated[activatable];
+
+    if !precondition {
+        return None;
+    }
+
+    let mut new_state = state.clone();
+    new_state.activated[activatable] = true;
+
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::TurnOn { actor, activatable },
+    ))
+}
+
+pub fn turn_off(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+    activatable: ActivatableId,
+) -> Option<Neighbor<State, Action>> {
+    let precondition = state.actors[actor].pos == world.activatable_pos[activatable]
+        && state.activated[activatable];
+
+    if !precondition {
+        return None;
+    }
+
+    let mut new_state = state.clone();
+    new_state.activated[activatable] = false;
+
+    Some(Neighbor::new(
+        new_state,
+        1.0,
+        Action::TurnOff { actor, activatable },
+    ))
+}
+
+// Lets running processes act: every holdable at the position of an
+// activatable that is on and fills things becomes filled. Waiting is only
+// offered when it would fill something.
+pub fn wait(state: &State, world: &World, actor: ActorId) -> Option<Neighbor<State, Action>> {
+    let mut new_state = state.clone();
+
+    for (activatable, &pos) in world.activatable_pos.iter().enumerate() {
+        if !(state.activated[activatable] && world.activatable_fills[activatable]) {
+            continue;
+        }
+
+        for (holdable, &holdable_pos) in state.holdable_pos.iter().enumerate() {
+            if holdable_pos == pos {
+                new_state.holdable_filled[holdable] = true;
+            }
+        }
+    }
+
+    if new_state == *state {
+        return None;
+    }
+
+    Some(Neighbor::new(new_state, 1.0, Action::Wait { actor }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::a_star::PlanError;
+    use crate::{run_scenario, PlannerResult};
+    use serde_json::{json, Value};
+
+    // Two rooms joined by a door, with the door's key, if it has one, in
+    // the far corner of the first room. The actor starts in the first room
+    // and has to reach the second.
+    fn scenario(door: &str, keys: usize) -> Value {
+        json!({
+            "world": {
+                "pos_move_groups": [1, 1, 1, 2, 2],
+                "door_side_a": [2],
+                "door_side_b": [3],
+                "key_pos": vec![0; keys],
+                "key_door": vec![0; keys],
+            },
+            "state": {
+                "actors": [{"pos": 1}],
+                "door_states": [door],
+            },
+            "objectives": [{"ActorPos": [0, 4]}],
+        })
+    }
+
+    fn plan(scenario: Value) -> PlannerResult {
+        run_scenario(serde_json::from_value(scenario).unwrap())
+    }
+
+    fn took(result: &PlannerResult, action: fn(&Action) -> bool) -> bool {
+        result.actions.iter().any(|step| action(&step.action))
+    }
+
+    #[test]
+    fn a_locked_door_is_opened_with_its_key() {
+        let result = plan(scenario("Locked", 1));
+        assert!(result.success);
+
+        // Fetch the key, unlock, open, go through and on
+        assert_eq!(result.total_cost, 7.0);
+        assert!(took(&result, |a| matches!(
+            a,
+            Action::PickUp { key: 0, .. }
+        )));
+        assert!(took(&result, |a| matches!(
+            a,
+            Action::UnlockDoor {
+                door: 0,
+                key: 0,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn a_door_is_locked_with_its_key() {
+        let mut scenario = scenario("Closed", 1);
+        scenario["objectives"] = json!([{"DoorState": [0, "Locked"]}]);
+        let result = plan(scenario);
+        assert!(result.success);
+        assert!(took(&result, |a| matches!(
+            a,
+            Action::LockDoor { door: 0, .. }
+        )));
+    }
+
+    #[test]
+    fn keys_only_fit_their_own_door() {
+        let world: World = serde_json::from_value(json!({
+            "pos_move_groups": [1, 2],
+            "door_side_a": [0, 0],
+            "door_side_b": [1, 1],
+            "key_pos": [0],
+            "key_door": [1],
+        }))
+        .unwrap();
+        let state: State = serde_json::from_value(json!({
+            "actors": [{"pos": 0, "inventory": [0]}],
+            "door_states": ["Locked", "Locked"],
+        }))
+        .unwrap();
+
+        assert!(unlock_door(&state, &world, 0, 0).is_none());
+        assert!(unlock_door(&state, &world, 0, 1).is_some());
+        assert!(pick_up(&state, &world, 0, 0).is_none());
+    }
+
+    #[test]
+    fn doors_are_broken_only_when_that_is_cheaper() {
+        // Going through with the key costs 7, and breaking the door 3 plus
+        // the cost of breaking it
+        for (break_cost, broken) in [(5.0, false), (3.5, true)] {
+            let mut scenario = scenario("Locked", 1);
+            scenario["world"]["break_door_cost"] = json!(break_cost);
+            let result = plan(scenario);
+
+            assert!(result.success);
+            assert_eq!(
+                took(&result, |a| matches!(a, Action::BreakDoor { door: 0, .. })),
+                broken,
+                "break cost {}",
+                break_cost
+            );
+            assert_eq!(
+                result.total_cost,
+                if broken { 3.0 + break_cost } else { 7.0 }
+            );
+        }
+
+        // Without a key there is no other way through
+        let result = plan(scenario("Locked", 0));
+        assert!(result.success);
+        assert!(took(&result, |a| matches!(a, Action::BreakDoor { .. })));
+    }
+
+    #[test]
+    fn a_broken_door_stays_broken() {
+        let mut scenario = scenario("Broken", 1);
+        scenario["state"]["actors"][0] = json!({"pos": 2, "inventory": [0]});
+        let world: World = serde_json::from_value(scenario["world"].clone()).unwrap();
+        let state: State = serde_json::from_value(scenario["state"].clone()).unwrap();
+
+        assert!(close_door(&state, &world, 0, 0).is_none());
+        assert!(lock_door(&state, &world, 0, 0).is_none());
+        assert!(break_door(&state, &world, 0, 0).is_none());
+        assert!(traverse_door(&state, &world, 0, 0).is_some());
+
+        scenario["objectives"] = json!([{"DoorState": [0, "Closed"]}]);
+        let result = plan(scenario);
+        assert!(!result.success);
+        assert_eq!(result.failure_reason(), Some(&PlanError::Unreachable));
+    }
+
+    #[test]
+    fn the_kitchen_is_planned_optimally() {
+        // Take the pot to the faucet, fill it, then put it on the stove
+        // and turn the stove on
+        for heuristic in ["GoalCount", "HMax"] {
+            let mut kitchen: Value = serde_json::from_str(include_str!("../kitchen.json")).unwrap();
+            kitchen["heuristic"] = json!(heuristic);
+            let result = plan(kitchen);
+
+            assert!(result.success);
+            assert_eq!(result.total_cost, 8.0, "{}", heuristic);
+            assert!(took(&result, |a| matches!(a, Action::Wait { .. })));
+        }
+    }
 }
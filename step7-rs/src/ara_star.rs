@@ -0,0 +1,430 @@
+use crate::a_star::{
+    reconstruct_path, Cost, Neighbor, Node, OpenEntry, PlanError, ProgressHandle, ReportProgress,
+    SearchLimits, SearchStats, Step,
+};
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Instant;
+
+/// A plan found by the anytime search
+#[derive(Debug, Clone)]
+pub struct AnytimePlan<S, A> {
+    pub steps: Vec<Step<S, A>>,
+    pub cost: Cost,
+    /// Factor by which `cost` can exceed the optimal cost, given an
+    /// admissible heuristic
+    pub bound: f32,
+}
+
+/// Anytime repairing A* (ARA*).
+///
+/// Searches with an inflated heuristic to find a plan quickly, then lowers
+/// the inflation and repairs the search to find cheaper plans, reusing the
+/// states already explored. Each item of the iterator is a plan at least as
+/// cheap as the one before with a tighter bound. Iteration ends once a plan
+/// is proven optimal, the search space is exhausted or a limit is reached.
+pub struct AraStar<'a, S, A, P = ProgressHandle> {
+    is_goal: &'a dyn Fn(&S) -> bool,
+    heuristic: &'a dyn Fn(&S) -> f32,
+    neighbors: &'a dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+    limits: &'a SearchLimits<P>,
+    weight: f32,
+    weight_step: f32,
+    nodes: Vec<Node<S, A>>,
+    index: HashMap<S, usize>,
+    open_set: BinaryHeap<OpenEntry>,
+    // Nodes improved after being expanded in the current pass
+    incons: Vec<usize>,
+    goal: Option<usize>,
+    seq: usize,
+    started: Instant,
+    stats: SearchStats,
+    error: Option<PlanError>,
+    // Cost and bound of the last plan handed out
+    last: Option<(Cost, f32)>,
+    done: bool,
+}
+
+impl<'a, S, A, P> AraStar<'a, S, A, P>
+where
+    S: Clone + PartialEq + Eq + std::hash::Hash + std::fmt::Debug,
+    A: Clone,
+    P: ReportProgress<S, A>,
+{
+    pub fn new(
+        start: &S,
+        is_goal: &'a dyn Fn(&S) -> bool,
+        heuristic: &'a dyn Fn(&S) -> f32,
+        neighbors: &'a dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+        initial_weight: f32,
+        weight_step: f32,
+        limits: &'a SearchLimits<P>,
+    ) -> AraStar<'a, S, A, P> {
+        let mut search = AraStar {
+            is_goal,
+            heuristic,
+            neighbors,
+            limits,
+            weight: initial_weight.max(1.0),
+            weight_step,
+            nodes: vec![],
+            index: HashMap::new(),
+            open_set: BinaryHeap::new(),
+            incons: vec![],
+            goal: None,
+            seq: 0,
+            started: Instant::now(),
+            stats: SearchStats::default(),
+            error: None,
+            last: None,
+            done: false,
+        };
+
+        let h = heuristic(start);
+        if h.is_nan() {
+            search.fail(PlanError::HeuristicNan);
+            return search;
+        }
+
+        search.index.insert(start.clone(), 0);
+        search.nodes.push(Node {
+            state: start.clone(),
+            g: 0.0,
+            h,
+            parent: None,
+            closed: false,
+        });
+
+        if is_goal(start) {
+            search.goal = Some(0);
+        } else {
+            search.push(0);
+        }
+
+        search
+    }
+
+    pub fn stats(&self) -> &SearchStats {
+        &self.stats
+    }
+
+    /// Why the search stopped without proving its last plan optimal, if it
+    /// did
+    pub fn error(&self) -> Option<&PlanError> {
+        self.error.as_ref()
+    }
+
+    fn plan(&mut self, goal: usize, bound: f32) -> AnytimePlan<S, A> {
+        let cost = self.nodes[goal].g;
+        self.stats.final_g = Some(cost);
+        self.last = Some((cost, bound));
+
+        AnytimePlan {
+            steps: reconstruct_path(&self.nodes, goal),
+            cost,
+            bound,
+        }
+    }
+
+    fn fail(&mut self, error: PlanError) {
+        self.error = Some(error);
+        self.done = true;
+    }
+
+    fn push(&mut self, node: usize) {
+        let Node { g, h, .. } = self.nodes[node];
+        self.seq += 1;
+        self.open_set.push(OpenEntry {
+            f: g + self.weight * h,
+            g,
+            seq: self.seq,
+            node,
+        });
+        self.stats.max_open_size = self.stats.max_open_size.max(self.open_set.len());
+    }
+
+    fn goal_f(&self) -> Cost {
+        self.goal.map_or(f32::INFINITY, |goal| {
+            self.nodes[goal].g + self.weight * self.nodes[goal].h
+        })
+    }
+
+    // Expand states until none left in the open set could lead to a plan
+    // cheaper than the current one under the current weight
+    fn improve_path(&mut self) -> Result<(), PlanError> {
+        while let Some(entry) = self.open_set.peek() {
+            let current = entry.node;
+
+            if self.nodes[current].closed || entry.g > self.nodes[current].g {
+                self.open_set.pop();
+                continue;
+            }
+
+            if self.goal_f() <= entry.f {
+                break;
+            }
+
+            let states = self.nodes.len();
+            if let Some(error) = self.limits.exceeded(self.started, &self.stats, states) {
+                return Err(error);
+            }
+            let (nodes, goal) = (&self.nodes, self.goal);
+            let open_size = self.open_set.len();
+            self.limits
+                .report(self.started, &self.stats, entry.f, open_size, || {
+                    goal.map(|goal| reconstruct_path(nodes, goal))
+                });
+
+            self.open_set.pop();
+            self.nodes[current].closed = true;
+            self.stats.expansions += 1;
+
+            let current_g = self.nodes[current].g;
+
+            for neighbor in (self.neighbors)(&self.nodes[current].state) {
+                self.stats.generated += 1;
+                let states = self.nodes.len();
+                let exceeded =
+                    self.limits
+                        .exceeded_while_expanding(self.started, &self.stats, states);
+                if let Some(error) = exceeded {
+                    return Err(error);
+                }
+
+                let tentative_g_score = current_g + neighbor.cost;
+
+                let next = match self.index.get(&neighbor.state) {
+                    Some(&i) => {
+                        if tentative_g_score >= self.nodes[i].g {
+                            self.stats.duplicates_pruned += 1;
+                            continue;
+                        }
+
+                        self.nodes[i].g = tentative_g_score;
+                        self.nodes[i].parent = Some((current, neighbor.action, neighbor.cost));
+                        i
+                    }
+                    None => {
+                        let h = (self.heuristic)(&neighbor.state);
+                        if h.is_nan() {
+                            return Err(PlanError::HeuristicNan);
+                        }
+
+                        let i = self.nodes.len();
+                        self.index.insert(neighbor.state.clone(), i);
+                        self.nodes.push(Node {
+                            state: neighbor.state,
+                            g: tentative_g_score,
+                            h,
+                            parent: Some((current, neighbor.action, neighbor.cost)),
+                            closed: false,
+                        });
+                        i
+                    }
+                };
+
+                // Paths through a goal state can't lead anywhere cheaper, so
+                // goal states are recorded rather than expanded.
+                if (self.is_goal)(&self.nodes[next].state) {
+                    let best_g = self.goal.map_or(f32::INFINITY, |goal| self.nodes[goal].g);
+                    if tentative_g_score < best_g {
+                        self.goal = Some(next);
+                    }
+                } else if self.nodes[next].closed {
+                    self.incons.push(next);
+                } else {
+                    self.push(next);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Lowest unweighted f among states that could still lead to a cheaper plan
+    fn lowest_f(&self) -> Cost {
+        let open = self
+            .open_set
+            .iter()
+            .filter(|entry| !self.nodes[entry.node].closed && entry.g <= self.nodes[entry.node].g)
+            .map(|entry| entry.node);
+
+        open.chain(self.incons.iter().copied())
+            .map(|node| self.nodes[node].g + self.nodes[node].h)
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    // Move the inconsistent states back to the open set, re-key everything
+    // under the current weight and start a fresh pass
+    fn restart_pass(&mut self) {
+        let nodes = &self.nodes;
+        let mut pending: Vec<usize> = self
+            .open_set
+            .drain()
+            .filter(|entry| !nodes[entry.node].closed && entry.g <= nodes[entry.node].g)
+            .map(|entry| entry.node)
+            .collect();
+        pending.append(&mut self.incons);
+        pending.sort_unstable();
+        pending.dedup();
+
+        for node in &mut self.nodes {
+            node.closed = false;
+        }
+
+        for node in pending {
+            self.push(node);
+        }
+    }
+}
+
+impl<'a, S, A, P> Iterator for AraStar<'a, S, A, P>
+where
+    S: Clone + PartialEq + Eq + std::hash::Hash + std::fmt::Debug,
+    A: Clone,
+    P: ReportProgress<S, A>,
+{
+    type Item = AnytimePlan<S, A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        if self.last.is_some() {
+            self.weight = (self.weight - self.weight_step).max(1.0);
+            self.restart_pass();
+        }
+
+        let result = self.improve_path();
+        self.stats.elapsed_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+
+        let goal = match (result, self.goal) {
+            (Ok(()), Some(goal)) => goal,
+            (Ok(()), None) => {
+                self.fail(PlanError::Unreachable);
+                return None;
+            }
+            (Err(error), goal) => {
+                self.fail(error);
+
+                // A pass cut short may still have found a cheaper plan, but
+                // it only carries the guarantee of the last complete pass.
+                let last_cost = self.last.map_or(f32::INFINITY, |(cost, _)| cost);
+                let goal = goal.filter(|&goal| self.nodes[goal].g < last_cost)?;
+                let bound = self.last.map_or(f32::INFINITY, |(_, bound)| bound);
+                return Some(self.plan(goal, bound));
+            }
+        };
+
+        let cost = self.nodes[goal].g;
+        let bound = self.weight.min(cost / self.lowest_f()).max(1.0);
+
+        // Nothing left can improve on this plan once the bound reaches one,
+        // or the weight can't be lowered any further
+        if bound <= 1.0 || self.weight <= 1.0 || self.weight_step <= 0.0 {
+            self.done = true;
+        }
+
+        Some(self.plan(goal, bound))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::a_star::a_star;
+    use crate::SearchMode;
+
+    type Cell = (i32, i32);
+
+    const SIZE: i32 = 12;
+
+    // A grid split in two by a wall across the middle. The wall has a
+    // muddy gap straight ahead of the start, which is slower than the open
+    // gap at the far end. The manhattan distance never overestimates.
+    fn neighbors(&(x, y): &Cell) -> Vec<Neighbor<Cell, ()>> {
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .map(|(dx, dy)| (x + dx, y + dy))
+            .filter(|&(x, y)| (0..SIZE).contains(&x) && (0..SIZE).contains(&y))
+            .filter_map(|(x, y)| {
+                let cost = if y != SIZE / 2 || x == SIZE - 1 {
+                    1.0
+                } else if x <= 2 {
+                    30.0
+                } else {
+                    return None;
+                };
+                Some(Neighbor::new((x, y), cost, ()))
+            })
+            .collect()
+    }
+
+    fn is_goal(cell: &Cell) -> bool {
+        *cell == (0, SIZE - 1)
+    }
+
+    fn distance(&(x, y): &Cell) -> f32 {
+        (x + (SIZE - 1 - y)) as f32
+    }
+
+    fn optimal_cost() -> Cost {
+        let limits: SearchLimits = SearchLimits::default();
+        let outcome = a_star(
+            &(0, 0),
+            &is_goal,
+            &distance,
+            &neighbors,
+            SearchMode::AStar,
+            &limits,
+        );
+        outcome.result.unwrap().last().unwrap().g
+    }
+
+    fn plans(initial_weight: f32, weight_step: f32) -> Vec<AnytimePlan<Cell, ()>> {
+        let limits: SearchLimits = SearchLimits::default();
+        let search = AraStar::new(
+            &(0, 0),
+            &is_goal,
+            &distance,
+            &neighbors,
+            initial_weight,
+            weight_step,
+            &limits,
+        );
+        search.collect()
+    }
+
+    #[test]
+    fn plans_never_get_more_expensive() {
+        let plans = plans(5.0, 0.5);
+        assert!(plans.len() > 1);
+
+        for pair in plans.windows(2) {
+            assert!(pair[1].cost <= pair[0].cost);
+            assert!(pair[1].bound <= pair[0].bound);
+        }
+    }
+
+    #[test]
+    fn plans_stay_within_their_bound() {
+        let optimal = optimal_cost();
+
+        for plan in plans(5.0, 0.5) {
+            assert!(plan.bound <= 5.0);
+            assert!(plan.cost <= plan.bound * optimal, "{:?}", plan.cost);
+            assert_eq!(plan.steps.last().unwrap().g, plan.cost);
+        }
+    }
+
+    #[test]
+    fn the_last_plan_is_optimal() {
+        let optimal = optimal_cost();
+
+        for (initial_weight, weight_step) in [(5.0, 0.5), (3.0, 1.0), (1.0, 0.5)] {
+            let last = plans(initial_weight, weight_step).pop().unwrap();
+            assert_eq!(last.cost, optimal);
+            assert_eq!(last.bound, 1.0);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::a_star::{SearchLimits, SearchMode};
+use crate::schema::Domain;
+use crate::{
+    run_scenario, Algorithm, Coordination, Heuristic, Objective, PlanProgress, PlannerResult,
+    Scenario, State, World,
+};
+use rayon::prelude::*;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+/// Scenarios to plan together: a JSON array of scenarios, or an object
+/// with one world and the problems to plan in it
+#[derive(Debug)]
+pub enum Batch {
+    Scenarios(Vec<Scenario>),
+    SharedWorld(Box<WorldBatch>),
+}
+
+/// Problems in one world, all planned with the same options
+#[derive(Deserialize, Debug)]
+pub struct WorldBatch {
+    pub world: World,
+    pub problems: Vec<Problem>,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    #[serde(default)]
+    pub mode: SearchMode,
+    #[serde(default)]
+    pub coordination: Coordination,
+    #[serde(default)]
+    pub heuristic: Heuristic,
+    /// Limits for each problem's search
+    #[serde(default)]
+    pub limits: SearchLimits<PlanProgress>,
+    #[serde(default)]
+    pub include_states: bool,
+    #[serde(default)]
+    pub domain: Domain,
+}
+
+/// Where to start in a WorldBatch's world and what to achieve
+#[derive(Deserialize, Debug)]
+pub struct Problem {
+    pub state: State,
+    pub objectives: Vec<Objective>,
+}
+
+impl<'de> Deserialize<'de> for Batch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Batch, D::Error> {
+        struct BatchVisitor;
+
+        impl<'de> Visitor<'de> for BatchVisitor {
+            type Value = Batch;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an array of scenarios or a world with problems")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Batch, A::Error> {
+                Vec::deserialize(SeqAccessDeserializer::new(seq)).map(Batch::Scenarios)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Batch, A::Error> {
+                WorldBatch::deserialize(MapAccessDeserializer::new(map))
+                    .map(|shared| Batch::SharedWorld(Box::new(shared)))
+            }
+        }
+
+        deserializer.deserialize_any(BatchVisitor)
+    }
+}
+
+impl Batch {
+    /// The batch as one scenario per problem, in order
+    pub fn into_scenarios(self) -> Vec<Scenario> {
+        let shared = match self {
+            Batch::Scenarios(scenarios) => return scenarios,
+            Batch::SharedWorld(shared) => shared,
+        };
+
+        let WorldBatch {
+            world,
+            problems,
+            algorithm,
+            mode,
+            coordination,
+            heuristic,
+            limits,
+            include_states,
+            domain,
+        } = *shared;
+
+        problems
+            .into_iter()
+            .map(|problem| Scenario {
+                world: world.clone(),
+                state: problem.state,
+                objectives: problem.objectives,
+                algorithm: algorithm.clone(),
+                mode,
+                coordination,
+                heuristic,
+                limits: limits.clone(),
+                include_states,
+                domain: domain.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Plan the scenarios in parallel, one per core at a time. The results are
+/// in the order of the scenarios.
+pub fn run_batch(scenarios: Vec<Scenario>) -> Vec<PlannerResult> {
+    scenarios.into_par_iter().map(run_scenario).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::{from_json, ErrorCode};
+
+    #[test]
+    fn invalid_scenarios_get_their_diagnostics() {
+        let batch: Batch = from_json(
+            r#"{
+                "world": {"pos_move_groups": [0, 0, 0], "door_side_a": [], "door_side_b": []},
+                "problems": [
+                    {"state": {"actors": [{"pos": 0}], "door_states": []},
+                     "objectives": [{"ActorPos": [0, 2]}]},
+                    {"state": {"actors": [{"pos": 7}], "door_states": []},
+                     "objectives": [{"ActorPos": [0, 2]}]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let results = run_batch(batch.into_scenarios());
+        assert!(results[0].success);
+        assert!(results[0].diagnostics().is_empty());
+
+        assert!(!results[1].success);
+        let diagnostics = results[1].diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, ErrorCode::UnknownId);
+        assert_eq!(diagnostics[0].path, "state.actors[0].pos");
+    }
+}
@@ -0,0 +1,279 @@
+use crate::a_star::Cost;
+use crate::actions::DoorState;
+use crate::{DoorId, PosId, PosMoveGroupId, State, World};
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// The rooms of a world joined by its doors, reduced to the positions on
+/// either side of a door. Shortest paths over it give a lower bound on the
+/// actions the actor needs to reach a position.
+#[derive(Debug)]
+pub struct DoorGraph {
+    pos_move_groups: Vec<PosMoveGroupId>,
+    door_sides: Vec<(PosId, PosId)>,
+    // Whether some key fits each door
+    has_key: Vec<bool>,
+    break_door_cost: Cost,
+    // The positions next to a door, and for each room the indices of those
+    // in it
+    nodes: Vec<PosId>,
+    node_of: Vec<Option<usize>>,
+    room_nodes: HashMap<PosMoveGroupId, Vec<usize>>,
+    // The doors next to each node
+    node_doors: Vec<Vec<DoorId>>,
+}
+
+// What a world's door graph depends on
+#[derive(PartialEq, Eq, Hash)]
+struct WorldKey {
+    pos_move_groups: Vec<PosMoveGroupId>,
+    door_side_a: Vec<PosId>,
+    door_side_b: Vec<PosId>,
+    key_door: Vec<DoorId>,
+    break_door_cost: u32,
+}
+
+// Graphs of the worlds seen so far. Cleared when it grows past this many,
+// which only matters for servers that see many distinct worlds.
+const CACHED_WORLDS: usize = 64;
+
+static GRAPHS: OnceLock<Mutex<HashMap<WorldKey, Arc<DoorGraph>>>> = OnceLock::new();
+
+// A node waiting to be settled, closest first
+#[derive(PartialEq)]
+pub(crate) struct Reached {
+    pub(crate) cost: Cost,
+    pub(crate) node: usize,
+}
+
+impl Eq for Reached {}
+
+impl Ord for Reached {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Reached {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl DoorGraph {
+    pub fn new(world: &World) -> DoorGraph {
+        let door_sides: Vec<(PosId, PosId)> = world
+            .door_side_a
+            .iter()
+            .copied()
+            .zip(world.door_side_b.iter().copied())
+            .collect();
+
+        let mut nodes = vec![];
+        let mut node_of = vec![None; world.pos_move_groups.len()];
+        let mut node_doors: Vec<Vec<DoorId>> = vec![];
+        let mut room_nodes: HashMap<PosMoveGroupId, Vec<usize>> = HashMap::new();
+
+        for (door, &(a, b)) in door_sides.iter().enumerate() {
+            for pos in [a, b] {
+                let node = *node_of[pos].get_or_insert_with(|| {
+                    nodes.push(pos);
+                    node_doors.push(vec![]);
+                    room_nodes
+                        .entry(world.pos_move_groups[pos])
+                        .or_default()
+                        .push(nodes.len() - 1);
+                    nodes.len() - 1
+                });
+                node_doors[node].push(door);
+            }
+        }
+
+        DoorGraph {
+            pos_move_groups: world.pos_move_groups.clone(),
+            has_key: (0..door_sides.len())
+                .map(|door| world.key_door.contains(&door))
+                .collect(),
+            door_sides,
+            break_door_cost: world.break_door_cost,
+            nodes,
+            node_of,
+            room_nodes,
+            node_doors,
+        }
+    }
+
+    /// The graph of `world`, built on first use and shared by every later
+    /// scenario with the same rooms and doors
+    pub fn for_world(world: &World) -> Arc<DoorGraph> {
+        let key = WorldKey {
+            pos_move_groups: world.pos_move_groups.clone(),
+            door_side_a: world.door_side_a.clone(),
+            door_side_b: world.door_side_b.clone(),
+            key_door: world.key_door.clone(),
+            break_door_cost: world.break_door_cost.to_bits(),
+        };
+
+        let graphs = GRAPHS.get_or_init(Default::default);
+        let mut graphs = graphs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(graph) = graphs.get(&key) {
+            return graph.clone();
+        }
+
+        if graphs.len() >= CACHED_WORLDS {
+            graphs.clear();
+        }
+
+        let graph = Arc::new(DoorGraph::new(world));
+        graphs.insert(key, graph.clone());
+        graph
+    }
+
+    // The fewest action costs to get through a door in its current state:
+    // traversing, after opening, unlocking or breaking it as needed
+    pub(crate) fn crossing_cost(&self, state: &State, door: DoorId) -> Cost {
+        let through = match state.door_states[door] {
+            DoorState::Open | DoorState::Broken => 0.0,
+            DoorState::Closed => self.break_door_cost.min(1.0),
+            DoorState::Locked if self.has_key[door] => self.break_door_cost.min(2.0),
+            DoorState::Locked => self.break_door_cost,
+        };
+
+        through + 1.0
+    }
+
+    /// A lower bound on the cost of moving the actor from `from` to `to`.
+    /// Doors in `free_doors` are counted as open, for when getting them into
+    /// some state is already counted elsewhere.
+    pub fn distance(&self, state: &State, from: PosId, to: PosId, free_doors: &[DoorId]) -> Cost {
+        let step = |a: PosId, b: PosId| if a == b { 0.0 } else { 1.0 };
+        let room = |pos: PosId| self.pos_move_groups[pos];
+
+        let mut best = if room(from) == room(to) {
+            step(from, to)
+        } else {
+            f32::INFINITY
+        };
+
+        let mut cost = vec![f32::INFINITY; self.nodes.len()];
+        let mut queue = BinaryHeap::new();
+
+        for &node in self.room_nodes.get(&room(from)).into_iter().flatten() {
+            cost[node] = step(from, self.nodes[node]);
+            queue.push(Reached {
+                cost: cost[node],
+                node,
+            });
+        }
+
+        while let Some(Reached { cost: c, node }) = queue.pop() {
+            if c > cost[node] {
+                continue;
+            }
+            if c >= best {
+                break;
+            }
+
+            let pos = self.nodes[node];
+            if room(pos) == room(to) {
+                best = best.min(c + step(pos, to));
+            }
+
+            let mut relax = |next: usize, next_cost: Cost| {
+                if next_cost < cost[next] {
+                    cost[next] = next_cost;
+                    queue.push(Reached {
+                        cost: next_cost,
+                        node: next,
+                    });
+                }
+            };
+
+            for &door in &self.node_doors[node] {
+                let (a, b) = self.door_sides[door];
+                let other = if pos == a { b } else { a };
+                let crossing = if free_doors.contains(&door) {
+                    1.0
+                } else {
+                    self.crossing_cost(state, door)
+                };
+
+                if let Some(next) = self.node_of[other] {
+                    relax(next, c + crossing);
+                }
+            }
+
+            for &next in &self.room_nodes[&room(pos)] {
+                relax(next, c + step(pos, self.nodes[next]));
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_scenario, Scenario};
+    use serde_json::json;
+
+    // A corridor of three rooms of three positions and a last room of
+    // one, joined by an open door, a closed one and a locked one without a
+    // key
+    fn corridor(from: PosId, to: PosId) -> Scenario {
+        serde_json::from_value(json!({
+            "world": {
+                "pos_move_groups": [1, 1, 1, 2, 2, 2, 3, 3, 3, 4],
+                "door_side_a": [2, 5, 8],
+                "door_side_b": [3, 6, 9],
+            },
+            "state": {
+                "actors": [{"pos": from}],
+                "door_states": ["Open", "Closed", "Locked"],
+            },
+            "objectives": [{"ActorPos": [0, to]}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn distances_along_a_corridor() {
+        let scenario = corridor(0, 0);
+        let graph = DoorGraph::new(&scenario.world);
+        let distance = |from, to| graph.distance(&scenario.state, from, to, &[]);
+
+        assert_eq!(distance(0, 0), 0.0);
+        assert_eq!(distance(0, 1), 1.0);
+        // To the door, then through it
+        assert_eq!(distance(0, 3), 2.0);
+        // Then to the next door, which has to be opened first
+        assert_eq!(distance(0, 6), 2.0 + 3.0);
+        // Then to the last, which has to be broken
+        assert_eq!(distance(0, 9), 5.0 + 1.0 + 5.0 + 1.0);
+        assert_eq!(distance(9, 0), distance(0, 9));
+
+        // A free door costs only the step through it
+        let free = graph.distance(&scenario.state, 0, 9, &[1, 2]);
+        assert_eq!(free, 3.0 * 2.0);
+    }
+
+    #[test]
+    fn distances_are_the_cost_of_walking() {
+        // Without keys to fetch, nothing is cheaper than the crossings
+        let graph = DoorGraph::new(&corridor(0, 0).world);
+
+        for from in 0..10 {
+            for to in 0..10 {
+                let scenario = corridor(from, to);
+                let distance = graph.distance(&scenario.state, from, to, &[]);
+                let result = run_scenario(scenario);
+                assert!(result.success);
+                assert_eq!(distance, result.total_cost, "from {} to {}", from, to);
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::a_star::{
+    a_star, Neighbor, PlanError, ReportProgress, SearchLimits, SearchMode, SearchOutcome,
+    SearchStats, Step,
+};
+use crate::actions::Action;
+use crate::door_graph::{DoorGraph, Reached};
+use crate::{is_goal, DoorId, Objective, PosId, PosMoveGroupId, State, World};
+use serde::Serialize;
+use std::collections::{BinaryHeap, HashMap};
+
+/// A stretch of a hierarchical plan
+#[derive(Debug, Clone, Serialize)]
+pub struct Leg {
+    pub from: PosMoveGroupId,
+    pub to: PosMoveGroupId,
+    /// The door the route crosses from one room to the next. None for the
+    /// last leg, which meets the objectives.
+    pub door: Option<DoorId>,
+}
+
+pub struct HierarchicalPlan {
+    pub outcome: SearchOutcome<State, Action>,
+    pub legs: Vec<Leg>,
+    /// The index into `legs` of each step of the outcome's plan, or of its
+    /// partial plan if it failed
+    pub step_legs: Vec<usize>,
+}
+
+/// Plan in two levels. A route over the rooms, joined by their doors, takes
+/// an actor to the room of their ActorPos objective. Each door on the route
+/// is a leg searched for on its own, with only the actions that keep the
+/// actor in the two rooms the door joins. A last search from the end of the
+/// route meets the objectives with every action available.
+///
+/// If a leg has no plan within its two rooms, the rest of the route is
+/// dropped and the last search starts from where the actor got to. The plan
+/// may cost more than an optimal one, most of all when other objectives lie
+/// back along the route.
+pub fn plan<P: ReportProgress<State, Action> + Clone>(
+    start: &State,
+    world: &World,
+    objectives: &[Objective],
+    heuristic: &dyn Fn(&State) -> f32,
+    neighbors: &dyn Fn(&State) -> Vec<Neighbor<State, Action>>,
+    mode: SearchMode,
+    limits: &SearchLimits<P>,
+) -> HierarchicalPlan {
+    let doors = DoorGraph::for_world(world);
+    let room = |pos: PosId| world.pos_move_groups[pos];
+
+    // The first actor with somewhere to be, and where
+    let (actor, goal_room) = objectives
+        .iter()
+        .find_map(|obj| match obj {
+            Objective::ActorPos(actor, pos) => Some((*actor, Some(room(*pos)))),
+            _ => None,
+        })
+        .unwrap_or((0, None));
+    let at = |s: &State| s.actors[actor].pos;
+
+    let route = goal_room
+        .and_then(|goal| route(world, &doors, start, at(start), goal))
+        .unwrap_or_default();
+
+    let mut plan = HierarchicalPlan {
+        outcome: SearchOutcome {
+            result: Ok(vec![]),
+            partial: None,
+            stats: SearchStats::default(),
+        },
+        legs: vec![],
+        step_legs: vec![],
+    };
+    let mut steps = vec![];
+    let mut state = start.clone();
+
+    for leg in route {
+        // Where the leg's door comes out, to head for
+        let door = leg.door.unwrap_or_default();
+        let (a, b) = (world.door_side_a[door], world.door_side_b[door]);
+        let target = if room(b) == leg.to { b } else { a };
+        let rooms = [leg.from, leg.to];
+
+        let outcome = a_star(
+            &state,
+            &|s| room(at(s)) == leg.to,
+            &|s| doors.distance(s, at(s), target, &[]),
+            &|s| {
+                neighbors(s)
+                    .into_iter()
+                    .filter(|n| rooms.contains(&room(at(&n.state))))
+                    .collect()
+            },
+            mode,
+            &limits.remaining(&plan.outcome.stats),
+        );
+        add_stats(&mut plan.outcome.stats, &outcome.stats);
+
+        match outcome.result {
+            Ok(path) => {
+                plan.legs.push(leg);
+                append(&mut steps, &mut plan.step_legs, path, plan.legs.len() - 1);
+                if let Some(step) = steps.last() {
+                    state = step.state.clone();
+                }
+            }
+            Err(PlanError::Unreachable) => break,
+            Err(error) => {
+                plan.legs.push(leg);
+                return failed(plan, steps, error, outcome.partial);
+            }
+        }
+    }
+
+    let outcome = a_star(
+        &state,
+        &|s| is_goal(s, objectives),
+        heuristic,
+        neighbors,
+        mode,
+        &limits.remaining(&plan.outcome.stats),
+    );
+    add_stats(&mut plan.outcome.stats, &outcome.stats);
+
+    let from = room(at(&state));
+    let to = match &outcome.result {
+        Ok(path) => path.last().map_or(from, |step| room(at(&step.state))),
+        Err(_) => from,
+    };
+    plan.legs.push(Leg {
+        from,
+        to,
+        door: None,
+    });
+
+    match outcome.result {
+        Ok(path) => {
+            append(&mut steps, &mut plan.step_legs, path, plan.legs.len() - 1);
+            plan.outcome.stats.final_g = Some(steps.last().map_or(0.0, |step| step.g));
+            plan.outcome.result = Ok(steps);
+            plan
+        }
+        Err(error) => failed(plan, steps, error, outcome.partial),
+    }
+}
+
+// The cheapest doors to cross from `from` to the room `goal`, with each
+// door costing what it takes to get through it in `state`
+fn route(
+    world: &World,
+    doors: &DoorGraph,
+    state: &State,
+    from: PosId,
+    goal: PosMoveGroupId,
+) -> Option<Vec<Leg>> {
+    let room = |pos: PosId| world.pos_move_groups[pos];
+
+    let mut adjacent: HashMap<PosMoveGroupId, Vec<(DoorId, PosMoveGroupId)>> = HashMap::new();
+    for door in 0..world.door_side_a.len() {
+        let (a, b) = (room(world.door_side_a[door]), room(world.door_side_b[door]));
+        if a != b {
+            adjacent.entry(a).or_default().push((door, b));
+            adjacent.entry(b).or_default().push((door, a));
+        }
+    }
+
+    let start = room(from);
+    let mut cost = HashMap::new();
+    let mut via: HashMap<PosMoveGroupId, Leg> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    cost.insert(start, 0.0);
+    queue.push(Reached {
+        cost: 0.0,
+        node: start,
+    });
+
+    while let Some(Reached { cost: c, node }) = queue.pop() {
+        if c > cost[&node] {
+            continue;
+        }
+
+        if node == goal {
+            let mut legs = vec![];
+            let mut at = goal;
+            while let Some(leg) = via.get(&at) {
+                at = leg.from;
+                legs.push(leg.clone());
+            }
+            legs.reverse();
+            return Some(legs);
+        }
+
+        for &(door, next) in adjacent.get(&node).into_iter().flatten() {
+            let next_cost = c + doors.crossing_cost(state, door);
+            if cost.get(&next).is_none_or(|&old| next_cost < old) {
+                cost.insert(next, next_cost);
+                via.insert(
+                    next,
+                    Leg {
+                        from: node,
+                        to: next,
+                        door: Some(door),
+                    },
+                );
+                queue.push(Reached {
+                    cost: next_cost,
+                    node: next,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+// Add a leg's steps to the plan, continuing its costs from the plan's
+fn append(
+    steps: &mut Vec<Step<State, Action>>,
+    step_legs: &mut Vec<usize>,
+    path: Vec<Step<State, Action>>,
+    leg: usize,
+) {
+    let g = steps.last().map_or(0.0, |step| step.g);
+
+    for mut step in path {
+        step.g += g;
+        steps.push(step);
+        step_legs.push(leg);
+    }
+}
+
+// End the plan with a leg's failure. A partial plan from the leg follows
+// on from the legs before it.
+fn failed(
+    mut plan: HierarchicalPlan,
+    mut steps: Vec<Step<State, Action>>,
+    error: PlanError,
+    partial: Option<Vec<Step<State, Action>>>,
+) -> HierarchicalPlan {
+    match partial {
+        Some(path) => {
+            let leg = plan.legs.len() - 1;
+            append(&mut steps, &mut plan.step_legs, path, leg);
+            plan.outcome.partial = Some(steps);
+        }
+        None => plan.step_legs.clear(),
+    }
+
+    plan.outcome.result = Err(error);
+    plan
+}
+
+fn add_stats(total: &mut SearchStats, leg: &SearchStats) {
+    total.expansions += leg.expansions;
+    total.generated += leg.generated;
+    total.duplicates_pruned += leg.duplicates_pruned;
+    total.max_open_size = total.max_open_size.max(leg.max_open_size);
+    total.elapsed_ms += leg.elapsed_ms;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{is_goal, run_scenario, Scenario};
+    use serde_json::json;
+
+    // Four rooms in a row, the middle door locked with its key in the
+    // first room. The actor has to reach the last room and leave the first
+    // door closed.
+    fn corridor() -> Scenario {
+        serde_json::from_value(json!({
+            "world": {
+                "pos_move_groups": [1, 1, 1, 2, 2, 3, 3, 4, 4],
+                "door_side_a": [2, 4, 6],
+                "door_side_b": [3, 5, 7],
+                "key_pos": [0],
+                "key_door": [1],
+            },
+            "state": {
+                "actors": [{"pos": 1}],
+                "door_states": ["Closed", "Locked", "Closed"],
+            },
+            "objectives": [{"ActorPos": [0, 8]}, {"DoorState": [0, "Closed"]}],
+            "algorithm": "Hierarchical",
+            "include_states": true,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn legs_join_up_and_reach_the_goal() {
+        let scenario = corridor();
+        let objectives = corridor().objectives;
+        let rooms = scenario.world.pos_move_groups.clone();
+        let start_room = rooms[scenario.state.actors[0].pos];
+
+        let result = run_scenario(scenario);
+        assert!(result.success);
+        let legs = result.legs.clone().unwrap();
+
+        // Each leg starts where the one before it ended, crossing a door
+        // until the last
+        assert_eq!(legs[0].from, start_room);
+        for pair in legs.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+            assert!(pair[0].door.is_some());
+        }
+        let last = legs.last().unwrap();
+        assert_eq!((last.to, last.door), (rooms[8], None));
+        assert_eq!(legs.len(), 4);
+
+        // The steps run through the legs in order, and each door leg ends
+        // in the room it leads to
+        let mut g = 0.0;
+        for (i, step) in result.actions.iter().enumerate() {
+            let leg = step.leg.unwrap();
+            let next_leg = result.actions.get(i + 1).map(|s| s.leg.unwrap());
+            assert!(next_leg.is_none_or(|next| next == leg || next == leg + 1));
+
+            let state = step.state.as_ref().unwrap();
+            if next_leg.is_some_and(|next| next != leg) {
+                assert_eq!(rooms[state.actors[0].pos], legs[leg].to);
+            }
+
+            g += step.cost;
+            assert_eq!(step.g, g);
+        }
+
+        let end = result.actions.last().unwrap().state.as_ref().unwrap();
+        assert!(is_goal(end, &objectives));
+        assert_eq!(result.total_cost, g);
+    }
+}
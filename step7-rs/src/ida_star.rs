@@ -0,0 +1,215 @@
+use crate::a_star::{
+    Cost, Neighbor, PlanError, ReportProgress, SearchLimits, SearchOutcome, SearchStats, Step,
+};
+use std::time::Instant;
+
+// A state on the current search path, with the neighbors still to try
+struct Frame<S, A> {
+    state: S,
+    g: Cost,
+    // Action and cost that led here from the previous frame
+    via: Option<(A, Cost)>,
+    neighbors: std::vec::IntoIter<Neighbor<S, A>>,
+}
+
+/// Iterative-deepening A*.
+///
+/// Runs depth-first searches bounded by f = g + h, raising the bound to the
+/// smallest f that exceeded it until a goal is found. Only the current path
+/// is kept in memory, at the cost of re-expanding states on every iteration.
+/// `max_open_size` in the returned stats is the deepest path reached.
+pub fn ida_star<S, A, P>(
+    start: &S,
+    is_goal: &dyn Fn(&S) -> bool,
+    heuristic: &dyn Fn(&S) -> f32,
+    neighbors: &dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+    limits: &SearchLimits<P>,
+) -> SearchOutcome<S, A>
+where
+    S: Clone + PartialEq + Eq + std::hash::Hash + std::fmt::Debug,
+    A: Clone,
+    P: ReportProgress<S, A>,
+{
+    let started = Instant::now();
+    let mut stats = SearchStats::default();
+
+    let outcome = |result, partial, mut stats: SearchStats| {
+        stats.elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        SearchOutcome {
+            result,
+            partial,
+            stats,
+        }
+    };
+
+    let heuristic = |state: &S| match heuristic(state) {
+        h if h.is_nan() => Err(PlanError::HeuristicNan),
+        h => Ok(h),
+    };
+
+    let start_h = match heuristic(start) {
+        Ok(h) => h,
+        Err(error) => return outcome(Err(error), None, stats),
+    };
+
+    if is_goal(start) {
+        stats.final_g = Some(0.0);
+        return outcome(Ok(vec![]), None, stats);
+    }
+
+    let mut bound = start_h;
+
+    // Path to the state with the lowest heuristic seen, kept as a partial
+    // plan in case a limit stops the search
+    let mut best: (Cost, Vec<Step<S, A>>) = (start_h, vec![]);
+
+    loop {
+        let mut next_bound = f32::INFINITY;
+
+        stats.expansions += 1;
+        let mut stack = vec![Frame {
+            state: start.clone(),
+            g: 0.0,
+            via: None,
+            neighbors: neighbors(start).into_iter(),
+        }];
+
+        while let Some(top) = stack.last_mut() {
+            let neighbor = match top.neighbors.next() {
+                Some(neighbor) => neighbor,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            stats.generated += 1;
+
+            let g = top.g + neighbor.cost;
+            if let Some(error) = limits.exceeded_while_expanding(started, &stats, stack.len()) {
+                return outcome(Err(error), Some(best.1), stats);
+            }
+
+            // Only the current path is remembered, so only cycles back onto
+            // it can be pruned
+            if stack.iter().any(|frame| frame.state == neighbor.state) {
+                stats.duplicates_pruned += 1;
+                continue;
+            }
+
+            let h = match heuristic(&neighbor.state) {
+                Ok(h) => h,
+                Err(error) => return outcome(Err(error), None, stats),
+            };
+
+            let f = g + h;
+            if f > bound {
+                next_bound = next_bound.min(f);
+                continue;
+            }
+
+            let found = is_goal(&neighbor.state);
+
+            if found || h < best.0 {
+                let mut path = path_of(&stack);
+                path.push(Step {
+                    action: neighbor.action.clone(),
+                    cost: neighbor.cost,
+                    g,
+                    state: neighbor.state.clone(),
+                });
+
+                if found {
+                    stats.final_g = Some(g);
+                    return outcome(Ok(path), None, stats);
+                }
+
+                best = (h, path);
+            }
+
+            if let Some(error) = limits.exceeded(started, &stats, stack.len()) {
+                return outcome(Err(error), Some(best.1), stats);
+            }
+            limits.report(started, &stats, bound, stack.len(), || Some(best.1.clone()));
+
+            stats.expansions += 1;
+            let successors = neighbors(&neighbor.state).into_iter();
+            stack.push(Frame {
+                state: neighbor.state,
+                g,
+                via: Some((neighbor.action, neighbor.cost)),
+                neighbors: successors,
+            });
+            stats.max_open_size = stats.max_open_size.max(stack.len());
+        }
+
+        if next_bound == f32::INFINITY {
+            return outcome(Err(PlanError::Unreachable), None, stats);
+        }
+
+        bound = next_bound;
+    }
+}
+
+fn path_of<S, A>(stack: &[Frame<S, A>]) -> Vec<Step<S, A>>
+where
+    S: Clone,
+    A: Clone,
+{
+    stack
+        .iter()
+        .filter_map(|frame| {
+            frame.via.as_ref().map(|(action, cost)| Step {
+                action: action.clone(),
+                cost: *cost,
+                g: frame.g,
+                state: frame.state.clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::a_star::{Budget, PlanError};
+    use crate::{run_scenario, Algorithm, Heuristic, Scenario};
+
+    fn scenario(json: &str, algorithm: Algorithm, heuristic: Heuristic) -> Scenario {
+        let mut scenario: Scenario = serde_json::from_str(json).unwrap();
+        scenario.algorithm = algorithm;
+        scenario.heuristic = heuristic;
+        scenario
+    }
+
+    const WORLDS: [&str; 3] = [
+        include_str!("../scenario.json"),
+        include_str!("../button.json"),
+        include_str!("../kitchen.json"),
+    ];
+
+    #[test]
+    fn plans_as_cheaply_as_a_star() {
+        for json in WORLDS {
+            for heuristic in [Heuristic::GoalCount, Heuristic::HMax] {
+                let a_star = run_scenario(scenario(json, Algorithm::AStar, heuristic));
+                let ida_star = run_scenario(scenario(json, Algorithm::IdaStar, heuristic));
+
+                assert!(a_star.success && ida_star.success);
+                assert_eq!(ida_star.total_cost, a_star.total_cost);
+                assert_eq!(ida_star.suboptimality_bound, Some(1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn stops_when_out_of_budget() {
+        let mut scenario = scenario(WORLDS[0], Algorithm::IdaStar, Heuristic::GoalCount);
+        scenario.limits.max_expansions = Some(5);
+
+        let result = run_scenario(scenario);
+        assert!(!result.success);
+        assert_eq!(
+            result.failure_reason(),
+            Some(&PlanError::BudgetExceeded(Budget::Expansions))
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::a_star::{Cost, Neighbor, SearchOutcome, Step};
+use crate::actions::Action;
+use crate::schema::GroundDomain;
+use crate::{ActivatableId, ActorId, DoorId, HoldableId, KeyId, State, World};
+
+/// The actions taken together in one joint step, each with its own cost
+pub type JointAction = Vec<(Action, Cost)>;
+
+// What an action has to itself for the length of a step
+#[derive(PartialEq)]
+enum Resource {
+    Door(DoorId),
+    Key(KeyId),
+    Holdable(HoldableId),
+    Activatable(ActivatableId),
+    // Waiting and schema actions may change anything
+    Everything,
+}
+
+fn resource(action: &Action) -> Option<Resource> {
+    if let Some(door) = action.door() {
+        return Some(Resource::Door(door));
+    }
+
+    match action {
+        Action::PickUp { key, .. } => Some(Resource::Key(*key)),
+        Action::Take { holdable, .. } | Action::PutDown { holdable, .. } => {
+            Some(Resource::Holdable(*holdable))
+        }
+        Action::TurnOn { activatable, .. } | Action::TurnOff { activatable, .. } => {
+            Some(Resource::Activatable(*activatable))
+        }
+        Action::Wait { .. } | Action::Schema { .. } => Some(Resource::Everything),
+        _ => None,
+    }
+}
+
+fn conflicts(a: &Action, b: &Action) -> bool {
+    match (resource(a), resource(b)) {
+        (Some(Resource::Everything), _) | (_, Some(Resource::Everything)) => true,
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Every joint step from `state`: each actor either idles or takes one of
+/// their actions, at least one actor acts, and no two actions conflict.
+///
+/// There are exponentially many in the number of actors, so this stops
+/// early, with the steps made so far, once `out_of_budget` says that the
+/// search could not use any more of them, e.g. because it was cancelled.
+/// It is asked with the number of steps made.
+pub fn joint_neighbors(
+    state: &State,
+    world: &World,
+    domain: &GroundDomain,
+    out_of_budget: &dyn Fn(usize) -> bool,
+) -> Vec<Neighbor<State, JointAction>> {
+    let options: Vec<Vec<Neighbor<State, Action>>> = (0..state.actors.len())
+        .map(|actor| domain.actor_neighbors(state, world, actor))
+        .collect();
+
+    let mut steps = vec![];
+    combine(state, &options, &mut vec![], &mut steps, out_of_budget);
+    steps
+}
+
+// Depth-first over the actors, choosing an option for each in turn.
+// Returns false once out of budget.
+fn combine<'a>(
+    state: &State,
+    options: &'a [Vec<Neighbor<State, Action>>],
+    chosen: &mut Vec<&'a Neighbor<State, Action>>,
+    steps: &mut Vec<Neighbor<State, JointAction>>,
+    out_of_budget: &dyn Fn(usize) -> bool,
+) -> bool {
+    let (mine, rest) = match options.split_first() {
+        Some(split) => split,
+        None => {
+            if !chosen.is_empty() {
+                steps.push(joint_step(state, chosen));
+            }
+            return !out_of_budget(steps.len());
+        }
+    };
+
+    // Idle
+    if !combine(state, rest, chosen, steps, out_of_budget) {
+        return false;
+    }
+
+    for option in mine {
+        if chosen.iter().any(|c| conflicts(&c.action, &option.action)) {
+            continue;
+        }
+
+        chosen.push(option);
+        let more = combine(state, rest, chosen, steps, out_of_budget);
+        chosen.pop();
+
+        if !more {
+            return false;
+        }
+    }
+
+    true
+}
+
+// The state after all the chosen actions. Actions that don't conflict
+// change different parts of the state, so each one's changes are copied
+// over in turn.
+fn joint_step(state: &State, chosen: &[&Neighbor<State, Action>]) -> Neighbor<State, JointAction> {
+    let mut joint = state.clone();
+
+    for option in chosen {
+        merge(&mut joint, state, &option.state, option.action.actor());
+    }
+
+    let cost = chosen.iter().map(|o| o.cost).fold(0.0, f32::max);
+    let actions = chosen.iter().map(|o| (o.action.clone(), o.cost)).collect();
+
+    Neighbor::new(joint, cost, actions)
+}
+
+fn merge(joint: &mut State, before: &State, after: &State, actor: ActorId) {
+    fn changes<T: PartialEq + Clone>(joint: &mut [T], before: &[T], after: &[T]) {
+        for (i, value) in after.iter().enumerate() {
+            if *value != before[i] {
+                joint[i] = value.clone();
+            }
+        }
+    }
+
+    joint.actors[actor] = after.actors[actor].clone();
+    changes(
+        &mut joint.door_states,
+        &before.door_states,
+        &after.door_states,
+    );
+    changes(
+        &mut joint.holdable_pos,
+        &before.holdable_pos,
+        &after.holdable_pos,
+    );
+    changes(
+        &mut joint.holdable_filled,
+        &before.holdable_filled,
+        &after.holdable_filled,
+    );
+    changes(&mut joint.activated, &before.activated, &after.activated);
+    changes(&mut joint.flags, &before.flags, &after.flags);
+}
+
+/// Spread a path of joint steps into their actions. Each action keeps its
+/// own cost and takes the `g` and state of its step. Also returns the step
+/// of each action.
+pub fn spread(path: Vec<Step<State, JointAction>>) -> (Vec<Step<State, Action>>, Vec<usize>) {
+    let mut steps = vec![];
+    let mut time_steps = vec![];
+
+    for (time, step) in path.into_iter().enumerate() {
+        for (action, cost) in step.action {
+            steps.push(Step {
+                action,
+                cost,
+                g: step.g,
+                state: step.state.clone(),
+            });
+            time_steps.push(time);
+        }
+    }
+
+    (steps, time_steps)
+}
+
+/// Spread a search's plan, or its partial plan if there is no plan, into
+/// their actions. Also returns the step of each action.
+pub fn flatten(
+    outcome: SearchOutcome<State, JointAction>,
+) -> (SearchOutcome<State, Action>, Vec<usize>) {
+    let mut time_steps = vec![];
+
+    let mut spread_path = |path| {
+        let (steps, times) = spread(path);
+        time_steps = times;
+        steps
+    };
+
+    let outcome = SearchOutcome {
+        result: outcome.result.map(&mut spread_path),
+        partial: outcome.partial.map(&mut spread_path),
+        stats: outcome.stats,
+    };
+
+    (outcome, time_steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actions::Action;
+    use crate::{run_scenario, Scenario, State};
+    use serde_json::json;
+
+    // Actor 0 carries a pot from 0 to 1 while actor 1, at 2, presses a
+    // button that only works while the pot is at 0, and sends it to 2
+    fn scenario() -> Scenario {
+        serde_json::from_value(json!({
+            "world": {"pos_move_groups": [0, 0, 0], "door_side_a": [], "door_side_b": []},
+            "state": {
+                "actors": [{"pos": 0, "holding": 0}, {"pos": 2}],
+                "door_states": [],
+                "holdable_pos": [0],
+                "holdable_filled": [false],
+                "flags": [false],
+            },
+            "objectives": [{"ActorPos": [0, 1]}, {"Flag": [0, true]}],
+            "coordination": "Joint",
+            "include_states": true,
+            "domain": {
+                "action_schemas": [{
+                    "name": "Press",
+                    "preconditions": [{"ActorPos": 2}, {"HoldablePos": [0, 0]}],
+                    "effects": [{"Flag": [0, true]}, {"HoldablePos": [0, 2]}],
+                }],
+            },
+        }))
+        .unwrap()
+    }
+
+    // Whether taking the actions one after another, in some order, from
+    // `before` ends in `after`
+    fn serializable(before: &State, actions: &[String], after: &State) -> bool {
+        if actions.is_empty() {
+            return before == after;
+        }
+
+        let scenario = scenario();
+        let domain = scenario
+            .domain
+            .ground(&scenario.world, &scenario.state, &scenario.limits)
+            .unwrap();
+
+        (0..actions.len()).any(|i| {
+            let mut rest = actions.to_vec();
+            let action = rest.remove(i);
+            domain
+                .neighbors(before, &scenario.world)
+                .into_iter()
+                .filter(|n| format!("{:?}", n.action) == action)
+                .any(|n| serializable(&n.state, &rest, after))
+        })
+    }
+
+    #[test]
+    fn joint_steps_can_be_taken_one_action_at_a_time() {
+        let result = run_scenario(scenario());
+        assert!(result.success);
+
+        let mut before = scenario().state;
+        let mut time = 0;
+        while let Some(first) = result
+            .actions
+            .iter()
+            .position(|a| a.time_step == Some(time))
+        {
+            let step: Vec<_> = result.actions[first..]
+                .iter()
+                .take_while(|a| a.time_step == Some(time))
+                .collect();
+            let actions: Vec<String> = step.iter().map(|a| format!("{:?}", a.action)).collect();
+            let after = step[0].state.clone().unwrap();
+
+            assert!(
+                step.len() == 1
+                    || step
+                        .iter()
+                        .all(|a| !matches!(a.action, Action::Wait { .. } | Action::Schema { .. })),
+                "{:?}",
+                actions
+            );
+            assert!(serializable(&before, &actions, &after), "{:?}", actions);
+            before = after;
+            time += 1;
+        }
+        assert!(time > 0);
+    }
+}
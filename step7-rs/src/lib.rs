@@ -0,0 +1,998 @@
+pub mod a_star;
+pub mod actions;
+pub mod ara_star;
+pub mod batch;
+pub mod door_graph;
+pub mod hierarchical;
+pub mod ida_star;
+pub mod joint;
+pub mod lpa_star;
+pub mod pddl;
+pub mod relaxed;
+pub mod replan;
+pub mod schema;
+pub mod strips;
+pub mod validation;
+
+use a_star::{
+    a_star, Cost, Neighbor, PlanError, Progress, ReportProgress, SearchLimits, SearchMode,
+    SearchOutcome, SearchStats, Step,
+};
+use actions::{
+    break_door, close_door, lock_door, move_actor, open_door, pick_up, put_down, take,
+    traverse_door, turn_off, turn_on, unlock_door, wait, Action, DoorState,
+};
+use ara_star::AraStar;
+use door_graph::DoorGraph;
+use hierarchical::Leg;
+use ida_star::ida_star;
+use joint::JointAction;
+use relaxed::{task_facts, RelaxedTask, ScenarioRelaxation};
+use schema::{Domain, GroundError};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use strips::{FactSet, StripsAction};
+use validation::{validate, Diagnostic};
+
+pub type PosId = usize;
+pub type DoorId = usize;
+pub type PosMoveGroupId = usize;
+pub type KeyId = usize;
+pub type HoldableId = usize;
+pub type ActivatableId = usize;
+pub type FlagId = usize;
+pub type ActorId = usize;
+
+/// One member of the squad
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub pos: PosId,
+    /// Keys the actor holds, in increasing order. Keys nobody holds are
+    /// still at their `World::key_pos`.
+    #[serde(default)]
+    pub inventory: Vec<KeyId>,
+    /// The holdable in the actor's hands, if any
+    #[serde(default)]
+    pub holding: Option<HoldableId>,
+}
+
+impl Actor {
+    pub fn holds(&self, key: KeyId) -> bool {
+        self.inventory.binary_search(&key).is_ok()
+    }
+
+    // Keeps the inventory sorted so that states holding the same keys
+    // compare equal however the keys were collected
+    fn pick_up(&mut self, key: KeyId) {
+        if let Err(i) = self.inventory.binary_search(&key) {
+            self.inventory.insert(i, key);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct State {
+    pub actors: Vec<Actor>,
+    pub door_states: Vec<DoorState>,
+    /// Where each holdable (a pot, a crate) is. A held holdable moves with
+    /// the actor holding it.
+    #[serde(default)]
+    pub holdable_pos: Vec<PosId>,
+    #[serde(default)]
+    pub holdable_filled: Vec<bool>,
+    /// Whether each activatable (a stove, a faucet) is on
+    #[serde(default)]
+    pub activated: Vec<bool>,
+    /// Designer-defined switches, such as a pressed button, that only
+    /// action schemas change
+    #[serde(default)]
+    pub flags: Vec<bool>,
+}
+
+impl State {
+    /// Whether any actor holds the key
+    pub fn held(&self, key: KeyId) -> bool {
+        self.actors.iter().any(|a| a.holds(key))
+    }
+
+    /// Whether any actor has the holdable in their hands
+    pub fn in_hands(&self, holdable: HoldableId) -> bool {
+        self.actors.iter().any(|a| a.holding == Some(holdable))
+    }
+
+    fn move_actor_to(&mut self, actor: ActorId, pos: PosId) {
+        self.actors[actor].pos = pos;
+
+        if let Some(holdable) = self.actors[actor].holding {
+            self.holdable_pos[holdable] = pos;
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct World {
+    pub pos_move_groups: Vec<PosMoveGroupId>,
+    pub door_side_a: Vec<DoorId>,
+    pub door_side_b: Vec<DoorId>,
+    /// Where each key lies before it is picked up
+    #[serde(default)]
+    pub key_pos: Vec<PosId>,
+    /// The door each key locks and unlocks
+    #[serde(default)]
+    pub key_door: Vec<DoorId>,
+    /// Cost of forcing a closed or locked door. Higher than the other
+    /// actions so that the planner only breaks doors it has no key for.
+    #[serde(default = "default_break_door_cost")]
+    pub break_door_cost: Cost,
+    /// Where each activatable is switched on and off
+    #[serde(default)]
+    pub activatable_pos: Vec<PosId>,
+    /// Whether each activatable, while on, fills the holdables at its
+    /// position when the actor waits, like a running faucet
+    #[serde(default)]
+    pub activatable_fills: Vec<bool>,
+}
+
+fn default_break_door_cost() -> Cost {
+    5.0
+}
+
+pub fn get_neighbors(state: &State, world: &World) -> Vec<Neighbor<State, Action>> {
+    (0..state.actors.len())
+        .flat_map(|actor| actor_neighbors(state, world, actor))
+        .collect()
+}
+
+/// The built-in actions one actor can take
+pub fn actor_neighbors(
+    state: &State,
+    world: &World,
+    actor: ActorId,
+) -> Vec<Neighbor<State, Action>> {
+    let mut actions = vec![];
+
+    for i in 0..world.pos_move_groups.len() {
+        actions.push(move_actor(state, world, actor, i));
+    }
+
+    for i in 0..state.door_states.len() {
+        actions.push(open_door(state, world, actor, i));
+        actions.push(close_door(state, world, actor, i));
+    }
+
+    for i in 0..state.door_states.len() {
+        actions.push(traverse_door(state, world, actor, i))
+    }
+
+    for i in 0..world.key_pos.len() {
+        actions.push(pick_up(state, world, actor, i));
+    }
+
+    for i in 0..state.door_states.len() {
+        actions.push(unlock_door(state, world, actor, i));
+        actions.push(lock_door(state, world, actor, i));
+        actions.push(break_door(state, world, actor, i));
+    }
+
+    for i in 0..state.holdable_pos.len() {
+        actions.push(take(state, actor, i));
+    }
+
+    actions.push(put_down(state, actor));
+
+    for i in 0..world.activatable_pos.len() {
+        actions.push(turn_on(state, world, actor, i));
+        actions.push(turn_off(state, world, actor, i));
+    }
+
+    // Waiting does the same whoever waits, so only the first actor does
+    if actor == 0 {
+        actions.push(wait(state, world, actor));
+    }
+
+    actions.into_iter().flatten().collect()
+}
+
+pub fn is_goal(state: &State, objectives: &[Objective]) -> bool {
+    objectives.iter().all(|obj| obj.is_satisfied(state))
+}
+
+/// Counts the unmet objectives, each costing `unit`, the domain's
+/// `GroundDomain::unit_cost`. With a door graph, an ActorPos objective
+/// counts the cost of walking there instead. Objectives one action may
+/// meet together, such as the position of an actor and of the holdable
+/// in their hands, count as much as the furthest of them. All counts its
+/// unmet objectives the same way and Any the closest of its own.
+pub fn heuristic(
+    state: &State,
+    objectives: &[Objective],
+    doors: Option<&DoorGraph>,
+    unit: Cost,
+) -> f32 {
+    // Whatever meets one of these may also be what lets the actor through
+    // the door, so it is not counted twice. With several actors, any of
+    // them may be the one to open a door, so only traversing it counts.
+    let mut pending_doors: Vec<DoorId> = vec![];
+    if state.actors.len() > 1 {
+        pending_doors.extend(0..state.door_states.len());
+    } else {
+        for obj in objectives.iter().filter(|obj| !obj.is_satisfied(state)) {
+            obj.named_doors(&mut pending_doors);
+        }
+    }
+
+    estimate(state, objectives, doors, &pending_doors, unit)
+}
+
+// The heuristic's estimate for objectives that must all be met. Those
+// sharing a variable are grouped, and each group counts its furthest.
+fn estimate(
+    state: &State,
+    objectives: &[Objective],
+    doors: Option<&DoorGraph>,
+    pending_doors: &[DoorId],
+    unit: Cost,
+) -> f32 {
+    let mut groups: Vec<(Vec<Variable>, f32)> = vec![];
+
+    for obj in objectives.iter().filter(|obj| !obj.is_satisfied(state)) {
+        let mut variables = vec![];
+        obj.variables(state, &mut variables);
+        let mut furthest = distance(state, obj, doors, pending_doors, unit);
+
+        // Merge every group this objective shares a variable with
+        let mut i = 0;
+        while i < groups.len() {
+            if groups[i].0.iter().any(|v| variables.contains(v)) {
+                let (shared, other) = groups.swap_remove(i);
+                variables.extend(shared);
+                furthest = furthest.max(other);
+            } else {
+                i += 1;
+            }
+        }
+
+        groups.push((variables, furthest));
+    }
+
+    groups.iter().map(|(_, furthest)| furthest).sum()
+}
+
+// The heuristic's estimate for one objective, 0 once it is met
+fn distance(
+    state: &State,
+    obj: &Objective,
+    doors: Option<&DoorGraph>,
+    pending_doors: &[DoorId],
+    unit: Cost,
+) -> f32 {
+    if obj.is_satisfied(state) {
+        return 0.0;
+    }
+
+    match (obj, doors) {
+        (Objective::ActorPos(actor, pos), Some(doors)) => {
+            doors.distance(state, state.actors[*actor].pos, *pos, pending_doors)
+        }
+        (Objective::All(objs), _) => estimate(state, objs, doors, pending_doors, unit),
+        (Objective::Any(objs), _) => objs
+            .iter()
+            .map(|o| distance(state, o, doors, pending_doors, unit))
+            .fold(f32::INFINITY, f32::min),
+        _ => unit,
+    }
+}
+
+// The parts of a state the heuristic groups objectives by. A built-in
+// action changes only one of them: moving an actor also moves the
+// holdable in their hands, which is why that holdable's position is the
+// actor's; waiting fills every holdable it can at once; and a holdable
+// nobody holds is moved by whoever takes it, so its position goes with
+// what the actors have in their hands.
+#[derive(Debug, PartialEq)]
+enum Variable {
+    ActorPos(ActorId),
+    Hands,
+    Door(DoorId),
+    Filled,
+    Activated(ActivatableId),
+    Holds(ActorId, KeyId),
+    Flag(FlagId),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum Objective {
+    ActorPos(ActorId, PosId),
+    DoorState(DoorId, DoorState),
+    HoldablePos(HoldableId, PosId),
+    Filled(HoldableId, bool),
+    Activated(ActivatableId, bool),
+    Holding(ActorId, Option<HoldableId>),
+    Holds(ActorId, KeyId, bool),
+    Flag(FlagId, bool),
+    /// The door is in any of the given states
+    DoorStateIn(DoorId, Vec<DoorState>),
+    Not(Box<Objective>),
+    /// At least one of the objectives is met
+    Any(Vec<Objective>),
+    /// Every one of the objectives is met
+    All(Vec<Objective>),
+}
+
+impl Objective {
+    pub fn is_satisfied(&self, state: &State) -> bool {
+        match self {
+            Objective::ActorPos(actor, pos_id) => state.actors[*actor].pos == *pos_id,
+            Objective::DoorState(door_id, door_state) => state.door_states[*door_id] == *door_state,
+            Objective::HoldablePos(holdable, pos_id) => state.holdable_pos[*holdable] == *pos_id,
+            Objective::Filled(holdable, filled) => state.holdable_filled[*holdable] == *filled,
+            Objective::Activated(activatable, on) => state.activated[*activatable] == *on,
+            Objective::Holding(actor, holding) => state.actors[*actor].holding == *holding,
+            Objective::Holds(actor, key, held) => state.actors[*actor].holds(*key) == *held,
+            Objective::Flag(flag, on) => state.flags[*flag] == *on,
+            Objective::DoorStateIn(door_id, door_states) => {
+                door_states.contains(&state.door_states[*door_id])
+            }
+            Objective::Not(obj) => !obj.is_satisfied(state),
+            Objective::Any(objs) => objs.iter().any(|obj| obj.is_satisfied(state)),
+            Objective::All(objs) => objs.iter().all(|obj| obj.is_satisfied(state)),
+        }
+    }
+
+    // The variables the objective is about
+    fn variables(&self, state: &State, variables: &mut Vec<Variable>) {
+        match self {
+            Objective::ActorPos(actor, _) => variables.push(Variable::ActorPos(*actor)),
+            Objective::HoldablePos(holdable, _) => {
+                let holder = state
+                    .actors
+                    .iter()
+                    .position(|a| a.holding == Some(*holdable));
+                variables.push(holder.map_or(Variable::Hands, Variable::ActorPos));
+            }
+            Objective::Holding(..) => variables.push(Variable::Hands),
+            Objective::DoorState(door, _) | Objective::DoorStateIn(door, _) => {
+                variables.push(Variable::Door(*door))
+            }
+            Objective::Filled(..) => variables.push(Variable::Filled),
+            Objective::Activated(activatable, _) => {
+                variables.push(Variable::Activated(*activatable))
+            }
+            Objective::Holds(actor, key, _) => variables.push(Variable::Holds(*actor, *key)),
+            Objective::Flag(flag, _) => variables.push(Variable::Flag(*flag)),
+            Objective::Not(obj) => obj.variables(state, variables),
+            Objective::Any(objs) | Objective::All(objs) => {
+                for obj in objs {
+                    obj.variables(state, variables);
+                }
+            }
+        }
+    }
+
+    // Every door the objective mentions
+    fn named_doors(&self, doors: &mut Vec<DoorId>) {
+        match self {
+            Objective::DoorState(door, _) | Objective::DoorStateIn(door, _) => doors.push(*door),
+            Objective::Not(obj) => obj.named_doors(doors),
+            Objective::Any(objs) | Objective::All(objs) => {
+                for obj in objs {
+                    obj.named_doors(doors);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn default_initial_weight() -> f32 {
+    3.0
+}
+
+fn default_weight_step() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+pub enum Algorithm {
+    /// A single search in the scenario's `mode`
+    #[default]
+    AStar,
+    /// Anytime repairing A*: a quick weighted plan first, then cheaper ones
+    /// with tighter bounds until the plan is proven optimal or the search's
+    /// time limit runs out
+    Anytime {
+        #[serde(default = "default_initial_weight")]
+        initial_weight: f32,
+        #[serde(default = "default_weight_step")]
+        weight_step: f32,
+    },
+    /// Iterative-deepening A*, which keeps only the current path in memory
+    /// and ignores the scenario's `mode`
+    IdaStar,
+    /// A route through the rooms first, then a search in the scenario's
+    /// `mode` for each leg of it. Faster on large maps, but with no bound
+    /// on the plan's cost.
+    Hierarchical,
+}
+
+/// How the actors' actions make up a plan
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Coordination {
+    /// One action of one actor at a time
+    #[default]
+    Sequential,
+    /// Steps in which any number of actors act at once, as long as no two
+    /// of them use the same door, key, holdable or activatable. A step
+    /// costs as much as its dearest action, so the plan's cost is how long
+    /// it takes. The search tries every combination of the actors'
+    /// actions, which suits small squads.
+    Joint,
+}
+
+/// The estimate of the remaining cost that guides the search
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Heuristic {
+    /// The number of objectives not yet met, with ActorPos objectives
+    /// counting the moves and door actions to get there. Each objective
+    /// counts less than 1 when some action is cheaper than that for what
+    /// it changes, so the count stays admissible.
+    #[default]
+    GoalCount,
+    /// The most expensive objective in the delete relaxation, where
+    /// actions never undo anything. Admissible.
+    HMax,
+    /// The sum of the objectives' costs in the delete relaxation. Better
+    /// informed than HMax but not admissible.
+    HAdd,
+    /// The cost of FF's relaxed plan, which counts actions shared between
+    /// objectives once. Not admissible.
+    RelaxedPlan,
+}
+
+impl Heuristic {
+    /// Whether the heuristic never overestimates, so that the search's
+    /// suboptimality bound holds
+    pub fn is_admissible(self) -> bool {
+        matches!(self, Heuristic::GoalCount | Heuristic::HMax)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Scenario {
+    pub world: World,
+    pub state: State,
+    pub objectives: Vec<Objective>,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    #[serde(default)]
+    pub mode: SearchMode,
+    #[serde(default)]
+    pub coordination: Coordination,
+    #[serde(default)]
+    pub heuristic: Heuristic,
+    #[serde(default)]
+    pub limits: SearchLimits<PlanProgress>,
+    /// Report the state after each step of the plan
+    #[serde(default)]
+    pub include_states: bool,
+    #[serde(default)]
+    pub domain: Domain,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanStep<S = State, A = Action> {
+    #[serde(flatten)]
+    action: A,
+    /// Cost of this action alone
+    cost: Cost,
+    /// Cost of the plan up to and including this action
+    g: Cost,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<S>,
+    /// The index in `legs` of the leg this action belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    leg: Option<usize>,
+    /// The joint step this action is part of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_step: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannerResult<S = State, A = Action> {
+    /// The plan, or the best partial plan if a search limit was reached
+    actions: Vec<PlanStep<S, A>>,
+    total_cost: Cost,
+    /// How far the plan's cost may be above optimal, as a factor. Null when
+    /// the search gives no guarantee.
+    suboptimality_bound: Option<f32>,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_reason: Option<PlanError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<SearchStats>,
+    /// The route of a hierarchical plan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    legs: Option<Vec<Leg>>,
+    /// What is wrong with the scenario, and where, if that kept it from
+    /// being planned
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<S, A> PlannerResult<S, A> {
+    /// Why no plan was found, if none was
+    pub fn failure_reason(&self) -> Option<&PlanError> {
+        self.failure_reason.as_ref()
+    }
+
+    /// What is wrong with the scenario, if that kept it from being planned
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    // A result for a problem that could not be searched at all
+    fn failed(error: PlanError, diagnostics: Vec<Diagnostic>) -> PlannerResult<S, A> {
+        PlannerResult {
+            actions: vec![],
+            total_cost: 0.0,
+            suboptimality_bound: None,
+            success: false,
+            failure_reason: Some(error),
+            stats: None,
+            legs: None,
+            diagnostics,
+        }
+    }
+}
+
+// Catch references the action functions would otherwise index out of bounds
+pub(crate) fn check_scenario(scenario: &Scenario) -> Result<(), PlanError> {
+    let problems = validate(scenario);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    Err(invalid_scenario(&problems))
+}
+
+pub(crate) fn invalid_scenario(problems: &[Diagnostic]) -> PlanError {
+    let reasons: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+    PlanError::InvalidScenario(reasons.join("; "))
+}
+
+/// Plan a scenario. One that fails validation, or whose action schemas
+/// cannot be grounded, is not searched, and its result lists the problems
+/// found.
+pub fn run_scenario(scenario: Scenario) -> PlannerResult {
+    let started = Instant::now();
+    let problems = validate(&scenario);
+    if !problems.is_empty() {
+        return PlannerResult::failed(invalid_scenario(&problems), problems);
+    }
+
+    let grounded = scenario
+        .domain
+        .ground(&scenario.world, &scenario.state, &scenario.limits);
+    let domain = match grounded {
+        Ok(domain) => domain,
+        Err(GroundError::Invalid(problems)) => {
+            return PlannerResult::failed(invalid_scenario(&problems), problems)
+        }
+        Err(GroundError::Stopped(error)) => return PlannerResult::failed(error, vec![]),
+    };
+
+    // Grounding counts against the search's time
+    let grounding = SearchStats {
+        elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+        ..SearchStats::default()
+    };
+    let limits = scenario.limits.remaining(&grounding);
+
+    let mut s0 = scenario.state;
+    for actor in &mut s0.actors {
+        actor.inventory.sort_unstable();
+    }
+    let w = scenario.world;
+    let o = scenario.objectives;
+
+    let chosen = scenario.heuristic;
+    let relaxation = match chosen {
+        Heuristic::GoalCount => None,
+        _ => Some(ScenarioRelaxation::new(&w, &s0, &domain, &o)),
+    };
+
+    // Schema actions might move the actor or open doors in ways the door
+    // graph doesn't know about
+    let doors = if domain.actions.is_empty() {
+        Some(DoorGraph::for_world(&w))
+    } else {
+        None
+    };
+
+    let unit = domain.unit_cost(&w);
+    let goal = |s: &State| is_goal(s, &o);
+    let h = |s: &State| match &relaxation {
+        Some(relaxation) => relaxation.estimate(chosen, s),
+        None => heuristic(s, &o, doors.as_deref(), unit),
+    };
+    let neighbors = |s: &State| domain.neighbors(s, &w);
+
+    // The legs of a hierarchical plan and the leg of each step
+    let mut hierarchy = None;
+    // The joint step of each step of a joint plan
+    let mut time_steps = None;
+
+    let (outcome, suboptimality_bound) = match (&scenario.algorithm, scenario.coordination) {
+        (Algorithm::Hierarchical, _) => {
+            let plan = hierarchical::plan(&s0, &w, &o, &h, &neighbors, scenario.mode, &limits);
+            hierarchy = Some((plan.legs, plan.step_legs));
+            (plan.outcome, None)
+        }
+        (algorithm, Coordination::Sequential) => search(
+            &s0,
+            &goal,
+            &h,
+            &neighbors,
+            algorithm,
+            scenario.mode,
+            &limits,
+        ),
+        (algorithm, Coordination::Joint) => {
+            // Every sequential plan's cost is at most the number of actors
+            // times the cost of a joint plan doing the same, so this keeps
+            // an admissible heuristic admissible
+            let actor_count = s0.actors.len() as f32;
+            let joint_h = |s: &State| h(s) / actor_count;
+
+            // A single state can have more joint steps than the limits
+            // allow, so making them is held to the limits too
+            let started = Instant::now();
+            let generated = Cell::new(0);
+            let joint_neighbors = |s: &State| {
+                let before = generated.get();
+                let out_of_budget = |made: usize| {
+                    let stats = SearchStats {
+                        generated: before + made,
+                        ..SearchStats::default()
+                    };
+                    limits.exceeded(started, &stats, 0).is_some()
+                };
+
+                let steps = joint::joint_neighbors(s, &w, &domain, &out_of_budget);
+                generated.set(before + steps.len());
+                steps
+            };
+
+            let (outcome, bound) = search(
+                &s0,
+                &goal,
+                &joint_h,
+                &joint_neighbors,
+                algorithm,
+                scenario.mode,
+                &limits,
+            );
+            let (outcome, times) = joint::flatten(outcome);
+            time_steps = Some(times);
+            (outcome, bound)
+        }
+    };
+
+    let suboptimality_bound = suboptimality_bound.filter(|_| chosen.is_admissible());
+    let mut result = planner_result(outcome, suboptimality_bound, scenario.include_states);
+
+    if let Some((legs, step_legs)) = hierarchy {
+        for (step, leg) in result.actions.iter_mut().zip(step_legs) {
+            step.leg = Some(leg);
+        }
+        result.legs = Some(legs);
+    }
+
+    if let Some(time_steps) = time_steps {
+        for (step, time) in result.actions.iter_mut().zip(time_steps) {
+            step.time_step = Some(time);
+        }
+    }
+
+    result
+}
+
+// Run one of the algorithms that search the whole problem at once. Their
+// suboptimality bound is returned alongside.
+fn search<A: Clone>(
+    start: &State,
+    is_goal: &dyn Fn(&State) -> bool,
+    heuristic: &dyn Fn(&State) -> f32,
+    neighbors: &dyn Fn(&State) -> Vec<Neighbor<State, A>>,
+    algorithm: &Algorithm,
+    mode: SearchMode,
+    limits: &SearchLimits<PlanProgress>,
+) -> (SearchOutcome<State, A>, Option<f32>)
+where
+    PlanProgress: ReportProgress<State, A>,
+{
+    match *algorithm {
+        // Hierarchical plans are made by hierarchical::plan, which searches
+        // its legs with A*
+        Algorithm::AStar | Algorithm::Hierarchical => (
+            a_star(start, is_goal, heuristic, neighbors, mode, limits),
+            mode.suboptimality_bound(),
+        ),
+        Algorithm::IdaStar => (
+            ida_star(start, is_goal, heuristic, neighbors, limits),
+            Some(1.0),
+        ),
+        Algorithm::Anytime {
+            initial_weight,
+            weight_step,
+        } => {
+            let mut search = AraStar::new(
+                start,
+                is_goal,
+                heuristic,
+                neighbors,
+                initial_weight,
+                weight_step,
+                limits,
+            );
+            let best = search.by_ref().last();
+            let stats = search.stats().clone();
+
+            match best {
+                Some(plan) => (
+                    SearchOutcome {
+                        result: Ok(plan.steps),
+                        partial: None,
+                        stats,
+                    },
+                    Some(plan.bound),
+                ),
+                None => (
+                    SearchOutcome {
+                        result: Err(search.error().cloned().unwrap_or(PlanError::Unreachable)),
+                        partial: None,
+                        stats,
+                    },
+                    None,
+                ),
+            }
+        }
+    }
+}
+
+fn plan_steps<S, A>(path: Vec<Step<S, A>>, include_states: bool) -> Vec<PlanStep<S, A>> {
+    path.into_iter()
+        .map(|step| PlanStep {
+            action: step.action,
+            cost: step.cost,
+            g: step.g,
+            state: if include_states {
+                Some(step.state)
+            } else {
+                None
+            },
+            leg: None,
+            time_step: None,
+        })
+        .collect()
+}
+
+pub(crate) fn planner_result<S, A>(
+    outcome: SearchOutcome<S, A>,
+    suboptimality_bound: Option<f32>,
+    include_states: bool,
+) -> PlannerResult<S, A> {
+    let (actions, success, failure_reason) = match outcome.result {
+        Ok(path) => (plan_steps(path, include_states), true, None),
+        Err(error) => (
+            outcome
+                .partial
+                .map(|path| plan_steps(path, include_states))
+                .unwrap_or_default(),
+            false,
+            Some(error),
+        ),
+    };
+
+    PlannerResult {
+        total_cost: actions.last().map_or(0.0, |step| step.g),
+        suboptimality_bound: suboptimality_bound.filter(|_| success),
+        actions,
+        success,
+        failure_reason,
+        stats: Some(outcome.stats),
+        legs: None,
+        diagnostics: vec![],
+    }
+}
+
+/// Shared view of a scenario's search progress, with the partial plan of
+/// the search's last update. Joint steps are spread into their actions.
+/// While a hierarchical plan is made, it is the partial plan of the current
+/// leg.
+#[derive(Debug, Clone, Default)]
+pub struct PlanProgress(Arc<Mutex<(Progress, Option<Vec<PlanStep>>)>>);
+
+impl PlanProgress {
+    pub fn new() -> PlanProgress {
+        PlanProgress::default()
+    }
+
+    /// The progress as of the search's last update
+    pub fn latest(&self) -> Progress {
+        self.lock().0.clone()
+    }
+
+    /// The progress as of the search's last update, with the partial plan
+    /// it had then, once the search has one
+    pub fn latest_with_plan(&self, include_states: bool) -> (Progress, Option<Vec<PlanStep>>) {
+        let (progress, mut steps) = self.lock().clone();
+        if !include_states {
+            for step in steps.iter_mut().flatten() {
+                step.state = None;
+            }
+        }
+        (progress, steps)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (Progress, Option<Vec<PlanStep>>)> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl ReportProgress<State, Action> for PlanProgress {
+    fn report(
+        &self,
+        progress: Progress,
+        partial: impl FnOnce() -> Option<Vec<Step<State, Action>>>,
+    ) {
+        let steps = partial().map(|path| plan_steps(path, true));
+        *self.lock() = (progress, steps);
+    }
+}
+
+impl ReportProgress<State, JointAction> for PlanProgress {
+    fn report(
+        &self,
+        progress: Progress,
+        partial: impl FnOnce() -> Option<Vec<Step<State, JointAction>>>,
+    ) {
+        let steps = partial().map(|path| {
+            let (path, time_steps) = joint::spread(path);
+            let mut steps = plan_steps(path, true);
+            for (step, time) in steps.iter_mut().zip(time_steps) {
+                step.time_step = Some(time);
+            }
+            steps
+        });
+        *self.lock() = (progress, steps);
+    }
+}
+
+/// Plan a PDDL problem with A*
+pub fn run_pddl(
+    domain: &str,
+    problem: &str,
+    heuristic: Heuristic,
+    limits: &SearchLimits,
+) -> PlannerResult<FactSet, StripsAction> {
+    let task = match pddl::parse(domain, problem) {
+        Ok(task) => task,
+        Err(error) => return PlannerResult::failed(error, vec![]),
+    };
+
+    let relaxation = RelaxedTask::from_task(&task);
+    let unit = task.unit_cost();
+    let h = |s: &FactSet| match heuristic {
+        Heuristic::GoalCount => task.goal_count(s) * unit,
+        _ => relaxation.estimate(heuristic, &task_facts(s)),
+    };
+
+    let outcome = a_star(
+        &task.init,
+        &|s| task.is_goal(s),
+        &h,
+        &|s| task.neighbors(s),
+        SearchMode::AStar,
+        limits,
+    );
+
+    planner_result(
+        task.name_actions(outcome),
+        SearchMode::AStar
+            .suboptimality_bound()
+            .filter(|_| heuristic.is_admissible()),
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn objective(json: serde_json::Value) -> Objective {
+        serde_json::from_value(json).unwrap()
+    }
+
+    // One actor at position 1, with a locked door and a flag that is set
+    fn state() -> State {
+        serde_json::from_value(json!({
+            "actors": [{"pos": 1}],
+            "door_states": ["Locked"],
+            "flags": [true],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn combined_objectives_are_met_as_expected() {
+        let state = state();
+        let met = |json| objective(json).is_satisfied(&state);
+
+        assert!(met(json!({"DoorStateIn": [0, ["Locked", "Broken"]]})));
+        assert!(!met(json!({"DoorStateIn": [0, ["Open", "Closed"]]})));
+        assert!(!met(json!({"DoorStateIn": [0, []]})));
+
+        assert!(met(json!({"Not": {"ActorPos": [0, 0]}})));
+        assert!(!met(json!({"Not": {"Flag": [0, true]}})));
+        assert!(met(json!({"Not": {"Not": {"Flag": [0, true]}}})));
+
+        assert!(met(
+            json!({"Any": [{"ActorPos": [0, 0]}, {"Flag": [0, true]}]})
+        ));
+        assert!(!met(
+            json!({"Any": [{"ActorPos": [0, 0]}, {"Flag": [0, false]}]})
+        ));
+        assert!(!met(json!({"Any": []})));
+
+        assert!(met(
+            json!({"All": [{"ActorPos": [0, 1]}, {"Flag": [0, true]}]})
+        ));
+        assert!(!met(
+            json!({"All": [{"ActorPos": [0, 1]}, {"Flag": [0, false]}]})
+        ));
+        assert!(met(json!({"All": []})));
+
+        assert!(met(json!({"All": [
+            {"Not": {"DoorState": [0, "Open"]}},
+            {"Any": [{"ActorPos": [0, 0]}, {"DoorStateIn": [0, ["Locked"]]}]},
+        ]})));
+    }
+
+    #[test]
+    fn the_cheapest_alternative_is_planned() {
+        // Two rooms joined by a closed door. Getting through it costs more
+        // than leaving it open.
+        let scenario = |heuristic: &str| -> Scenario {
+            serde_json::from_value(json!({
+                "world": {
+                    "pos_move_groups": [1, 1, 2],
+                    "door_side_a": [1],
+                    "door_side_b": [2],
+                },
+                "state": {"actors": [{"pos": 0}], "door_states": ["Closed"]},
+                "objectives": [{"Any": [
+                    {"ActorPos": [0, 2]},
+                    {"DoorStateIn": [0, ["Open", "Broken"]]},
+                ]}],
+                "heuristic": heuristic,
+            }))
+            .unwrap()
+        };
+
+        for heuristic in ["GoalCount", "HMax", "HAdd", "RelaxedPlan"] {
+            let result = run_scenario(scenario(heuristic));
+            assert!(result.success);
+            assert_eq!(result.total_cost, 2.0, "{}", heuristic);
+            assert!(matches!(
+                result.actions.last().unwrap().action,
+                Action::OpenDoor { .. }
+            ));
+        }
+    }
+}
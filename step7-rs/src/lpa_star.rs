@@ -0,0 +1,395 @@
+use crate::a_star::{
+    Cost, Neighbor, PlanError, ReportProgress, SearchLimits, SearchOutcome, SearchStats, Step,
+};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Instant;
+
+type Key = (Cost, Cost);
+
+struct LpaNode<S, A> {
+    state: S,
+    g: Cost,
+    // One-step lookahead of g, from the best predecessor
+    rhs: Cost,
+    h: Cost,
+    goal: bool,
+    // Predecessor rhs came from, with the action and cost of its edge
+    parent: Option<(usize, A, Cost)>,
+    // Outgoing edges, generated the first time the node is expanded
+    succs: Option<Vec<(usize, A, Cost)>>,
+    preds: Vec<usize>,
+    // Key the node is queued under while it is locally inconsistent
+    queued: Option<Key>,
+}
+
+struct KeyEntry {
+    key: Key,
+    seq: usize,
+    node: usize,
+}
+
+impl PartialEq for KeyEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for KeyEntry {}
+
+impl PartialOrd for KeyEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for KeyEntry {
+    // Lowest key first, ties first-in first-out
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .partial_cmp(&self.key)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Lifelong planning A* (LPA*).
+///
+/// Keeps its search from one call to the next. When edges change, telling
+/// the planner which states' outgoing actions may differ lets it repair
+/// only the affected part of the search instead of starting over. The
+/// start can be moved too, e.g. as the plan is carried out, and the search
+/// is repaired from the new start in the same way.
+pub struct LpaStar<S, A> {
+    start: S,
+    // Empty until the first call to plan, which adds the start
+    nodes: Vec<LpaNode<S, A>>,
+    // Index of the start's node, whose rhs is always 0
+    root: usize,
+    index: HashMap<S, usize>,
+    open_set: BinaryHeap<KeyEntry>,
+    goals: Vec<usize>,
+    seq: usize,
+}
+
+impl<S, A> LpaStar<S, A>
+where
+    S: Clone + PartialEq + Eq + std::hash::Hash + std::fmt::Debug,
+    A: Clone,
+{
+    pub fn new(start: &S) -> LpaStar<S, A> {
+        LpaStar {
+            start: start.clone(),
+            nodes: vec![],
+            root: 0,
+            index: HashMap::new(),
+            open_set: BinaryHeap::new(),
+            goals: vec![],
+            seq: 0,
+        }
+    }
+
+    /// Find a plan from the start, reusing whatever is left of earlier
+    /// searches. The stats count only the work done by this call.
+    pub fn plan<P: ReportProgress<S, A>>(
+        &mut self,
+        is_goal: &dyn Fn(&S) -> bool,
+        heuristic: &dyn Fn(&S) -> f32,
+        neighbors: &dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+        limits: &SearchLimits<P>,
+    ) -> SearchOutcome<S, A> {
+        let started = Instant::now();
+        let mut stats = SearchStats::default();
+        let result =
+            self.compute_shortest_path(is_goal, heuristic, neighbors, limits, started, &mut stats);
+
+        let result = result.and_then(|()| match self.best_goal() {
+            Some((goal, _)) if self.nodes[goal].g < f32::INFINITY => {
+                stats.final_g = Some(self.nodes[goal].g);
+                Ok(self.path_to(goal))
+            }
+            _ => Err(PlanError::Unreachable),
+        });
+
+        stats.elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        SearchOutcome {
+            result,
+            partial: None,
+            stats,
+        }
+    }
+
+    /// Regenerate the outgoing actions of every explored state for which
+    /// `affected` is true, e.g. after the world the neighbors are generated
+    /// from has changed. The next call to `plan` repairs the search.
+    pub fn update(
+        &mut self,
+        affected: &dyn Fn(&S) -> bool,
+        is_goal: &dyn Fn(&S) -> bool,
+        heuristic: &dyn Fn(&S) -> f32,
+        neighbors: &dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+    ) -> Result<(), PlanError> {
+        for u in 0..self.nodes.len() {
+            if self.nodes[u].succs.is_none() || !affected(&self.nodes[u].state) {
+                continue;
+            }
+
+            let old = self.nodes[u].succs.take().unwrap_or_default();
+            let new = self.successors(u, is_goal, heuristic, neighbors)?;
+
+            for (v, _, _) in &old {
+                if !new.iter().any(|(w, _, _)| w == v) {
+                    self.nodes[*v].preds.retain(|&p| p != u);
+                }
+            }
+
+            let mut targets: Vec<usize> = old.iter().chain(&new).map(|(v, _, _)| *v).collect();
+            targets.sort_unstable();
+            targets.dedup();
+
+            self.nodes[u].succs = Some(new);
+
+            for v in targets {
+                self.update_vertex(v);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Plan from `start` from now on. The g values found so far are kept,
+    /// and the next call to `plan` repairs the ones that the move changed.
+    pub fn move_start(
+        &mut self,
+        start: &S,
+        is_goal: &dyn Fn(&S) -> bool,
+        heuristic: &dyn Fn(&S) -> f32,
+    ) -> Result<(), PlanError> {
+        self.start = start.clone();
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+
+        let old = self.root;
+        let new = self.node_for(start.clone(), is_goal, heuristic)?;
+        if new == old {
+            return Ok(());
+        }
+
+        // The old start now takes its rhs from its predecessors like any
+        // other state
+        self.root = new;
+        self.nodes[new].rhs = 0.0;
+        self.nodes[new].parent = None;
+        self.update_vertex(new);
+        self.update_vertex(old);
+        Ok(())
+    }
+
+    fn key(&self, node: usize) -> Key {
+        let node = &self.nodes[node];
+        let g = node.g.min(node.rhs);
+        (g + node.h, g)
+    }
+
+    fn update_vertex(&mut self, v: usize) {
+        if v != self.root {
+            let mut best: Option<(Cost, usize, A, Cost)> = None;
+
+            for &u in &self.nodes[v].preds {
+                let edges = self.nodes[u].succs.iter().flatten();
+                for (_, action, cost) in edges.filter(|(w, _, _)| *w == v) {
+                    let rhs = self.nodes[u].g + cost;
+                    if best.as_ref().is_none_or(|(b, ..)| rhs < *b) {
+                        best = Some((rhs, u, action.clone(), *cost));
+                    }
+                }
+            }
+
+            let node = &mut self.nodes[v];
+            match best {
+                Some((rhs, u, action, cost)) if rhs < f32::INFINITY => {
+                    node.rhs = rhs;
+                    node.parent = Some((u, action, cost));
+                }
+                _ => {
+                    node.rhs = f32::INFINITY;
+                    node.parent = None;
+                }
+            }
+        }
+
+        if self.nodes[v].g != self.nodes[v].rhs {
+            let key = self.key(v);
+            self.nodes[v].queued = Some(key);
+            self.seq += 1;
+            self.open_set.push(KeyEntry {
+                key,
+                seq: self.seq,
+                node: v,
+            });
+        } else {
+            self.nodes[v].queued = None;
+        }
+    }
+
+    fn node_for(
+        &mut self,
+        state: S,
+        is_goal: &dyn Fn(&S) -> bool,
+        heuristic: &dyn Fn(&S) -> f32,
+    ) -> Result<usize, PlanError> {
+        if let Some(&i) = self.index.get(&state) {
+            return Ok(i);
+        }
+
+        let h = heuristic(&state);
+        if h.is_nan() {
+            return Err(PlanError::HeuristicNan);
+        }
+
+        let i = self.nodes.len();
+        let goal = is_goal(&state);
+        if goal {
+            self.goals.push(i);
+        }
+
+        self.index.insert(state.clone(), i);
+        self.nodes.push(LpaNode {
+            state,
+            g: f32::INFINITY,
+            rhs: f32::INFINITY,
+            h,
+            goal,
+            parent: None,
+            succs: None,
+            preds: vec![],
+            queued: None,
+        });
+        Ok(i)
+    }
+
+    fn successors(
+        &mut self,
+        u: usize,
+        is_goal: &dyn Fn(&S) -> bool,
+        heuristic: &dyn Fn(&S) -> f32,
+        neighbors: &dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+    ) -> Result<Vec<(usize, A, Cost)>, PlanError> {
+        let mut succs = vec![];
+
+        for neighbor in neighbors(&self.nodes[u].state) {
+            let v = self.node_for(neighbor.state, is_goal, heuristic)?;
+            if !self.nodes[v].preds.contains(&u) {
+                self.nodes[v].preds.push(u);
+            }
+            succs.push((v, neighbor.action, neighbor.cost));
+        }
+
+        Ok(succs)
+    }
+
+    // Known goal with the lowest key
+    fn best_goal(&self) -> Option<(usize, Key)> {
+        self.goals
+            .iter()
+            .map(|&goal| (goal, self.key(goal)))
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+    }
+
+    fn compute_shortest_path<P: ReportProgress<S, A>>(
+        &mut self,
+        is_goal: &dyn Fn(&S) -> bool,
+        heuristic: &dyn Fn(&S) -> f32,
+        neighbors: &dyn Fn(&S) -> Vec<Neighbor<S, A>>,
+        limits: &SearchLimits<P>,
+        started: Instant,
+        stats: &mut SearchStats,
+    ) -> Result<(), PlanError> {
+        if self.nodes.is_empty() {
+            let start = self.node_for(self.start.clone(), is_goal, heuristic)?;
+            self.root = start;
+            self.nodes[start].rhs = 0.0;
+            self.update_vertex(start);
+        }
+
+        while let Some(entry) = self.open_set.peek() {
+            let u = entry.node;
+            let key = entry.key;
+
+            if self.nodes[u].queued != Some(key) {
+                self.open_set.pop();
+                continue;
+            }
+
+            if let Some((goal, goal_key)) = self.best_goal() {
+                let consistent = self.nodes[goal].g == self.nodes[goal].rhs;
+                if consistent && key >= goal_key {
+                    break;
+                }
+            }
+
+            if let Some(error) = limits.exceeded(started, stats, self.nodes.len()) {
+                return Err(error);
+            }
+            let open_size = self.open_set.len();
+            limits.report(started, stats, key.0, open_size, || None);
+
+            self.open_set.pop();
+            self.nodes[u].queued = None;
+            stats.expansions += 1;
+
+            if self.nodes[u].succs.is_none() && !self.nodes[u].goal {
+                let succs = self.successors(u, is_goal, heuristic, neighbors)?;
+                stats.generated += succs.len();
+                self.nodes[u].succs = Some(succs);
+            }
+
+            let targets: Vec<usize> = self.nodes[u]
+                .succs
+                .iter()
+                .flatten()
+                .map(|(v, _, _)| *v)
+                .collect();
+
+            if self.nodes[u].g > self.nodes[u].rhs {
+                self.nodes[u].g = self.nodes[u].rhs;
+            } else {
+                self.nodes[u].g = f32::INFINITY;
+                self.update_vertex(u);
+            }
+
+            for v in targets {
+                self.update_vertex(v);
+            }
+
+            stats.max_open_size = stats.max_open_size.max(self.open_set.len());
+        }
+
+        Ok(())
+    }
+
+    fn path_to(&self, goal: usize) -> Vec<Step<S, A>> {
+        let mut total_path = vec![];
+        let mut current = goal;
+
+        // Bounded by the node count in case the parent links ever loop
+        while let Some((parent, action, cost)) = &self.nodes[current].parent {
+            if total_path.len() > self.nodes.len() {
+                break;
+            }
+
+            total_path.push(Step {
+                action: action.clone(),
+                cost: *cost,
+                g: self.nodes[current].g,
+                state: self.nodes[current].state.clone(),
+            });
+            current = *parent;
+        }
+
+        total_path.reverse();
+        total_path
+    }
+}
@@ -1,144 +1,820 @@
 #[macro_use]
 extern crate rocket;
 
-mod a_star;
-mod actions;
-
-use a_star::{a_star, Neighbor};
-use actions::{close_door, move_actor, open_door, traverse_door, Action, DoorState};
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
-use rocket::{Build, Rocket};
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::timeout;
+use rocket::{Build, Request, Rocket};
+use rustplan::a_star::{CancelToken, Progress, SearchLimits};
+use rustplan::batch::{run_batch, Batch};
+use rustplan::validation::{from_json, validate, Diagnostic, ErrorCode};
+use rustplan::{
+    run_pddl, run_scenario, Heuristic, PlanProgress, PlanStep, PlannerResult, Scenario,
+};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Debug;
 use std::fs::read_to_string;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Server-wide settings, read from Rocket's configuration
+#[derive(Deserialize)]
+struct PlannerConfig {
+    /// Caps applied on top of whatever limits a posted scenario asks for
+    #[serde(default)]
+    planner_limits: SearchLimits<PlanProgress>,
+    /// How long a finished job is kept for GET /jobs/<id>, in seconds
+    #[serde(default = "default_finished_job_ttl_secs")]
+    finished_job_ttl_secs: u64,
+    /// Jobs that may run at once. POST /jobs is refused while this many
+    /// are running.
+    #[serde(default = "default_max_running_jobs")]
+    max_running_jobs: usize,
+}
+
+fn default_finished_job_ttl_secs() -> u64 {
+    600
+}
 
-type PosId = usize;
-type DoorId = usize;
-type PosMoveGroupId = usize;
+// One per CPU, as a job keeps a thread busy until it finishes
+fn default_max_running_jobs() -> usize {
+    std::thread::available_parallelism().map_or(4, |cpus| cpus.get())
+}
+
+// Cancels the search if the request is dropped before it finishes, e.g.
+// because the client disconnected
+struct CancelOnDrop(CancelToken);
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
-pub struct State {
-    pub actor_pos: PosId,
-    pub door_states: Vec<DoorState>,
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct World {
-    pub pos_move_groups: Vec<PosMoveGroupId>,
-    pub door_side_a: Vec<DoorId>,
-    pub door_side_b: Vec<DoorId>,
+fn read(filename: &str) -> Result<String, String> {
+    read_to_string(filename).map_err(|error| format!("{}: {}", filename, error))
 }
 
-fn get_neighbors(state: &State, world: &World) -> Vec<Neighbor<State, Action>> {
-    let mut actions = vec![];
+fn parse<T: DeserializeOwned>(filename: &str, data: &str) -> Result<T, String> {
+    from_json(data).map_err(|diagnostic| format!("{}: {}", filename, diagnostic))
+}
 
-    for i in 0..world.pos_move_groups.len() {
-        actions.push(move_actor(state, world, i));
+fn run_scenario_from_file(
+    scenario_filename: &str,
+    domain_filename: Option<&str>,
+) -> Result<(), String> {
+    // PDDL comes as a domain file followed by a problem file
+    if scenario_filename.ends_with(".pddl") {
+        let domain = read(scenario_filename)?;
+        let problem_filename = domain_filename.ok_or("a PDDL domain needs a problem file")?;
+        let problem = read(problem_filename)?;
+        let result = run_pddl(&domain, &problem, Heuristic::HMax, &SearchLimits::default());
+        return finish(result);
     }
 
-    for i in 0..state.door_states.len() {
-        actions.push(open_door(state, world, i));
-        actions.push(close_door(state, world, i));
+    let mut scenario: Scenario = parse(scenario_filename, &read(scenario_filename)?)?;
+
+    // A domain file replaces the scenario's own domain
+    if let Some(domain_filename) = domain_filename {
+        scenario.domain = parse(domain_filename, &read(domain_filename)?)?;
     }
 
-    for i in 0..state.door_states.len() {
-        actions.push(traverse_door(state, world, i))
+    let problems = validate(&scenario);
+    if !problems.is_empty() {
+        let lines: Vec<String> = problems
+            .iter()
+            .map(|problem| format!("{}: {}", scenario_filename, problem))
+            .collect();
+        return Err(lines.join("\n"));
     }
 
-    actions.into_iter().flatten().collect()
+    finish(run_scenario(scenario))
 }
 
-fn heuristic(state: &State, objectives: &[Objective]) -> f32 {
-    let mut distance = 0.0;
+// Print a result, failing with the reason if it has no plan so that the
+// process exits non-zero
+fn finish<S: Debug, A: Debug>(result: PlannerResult<S, A>) -> Result<(), String> {
+    let error = result.failure_reason().map(|error| error.to_string());
+    dbg!(result);
+    error.map_or(Ok(()), Err)
+}
 
-    for obj in objectives {
-        match obj {
-            Objective::ActorPos(pos_id) => {
-                if state.actor_pos != *pos_id {
-                    distance += 1.0;
-                }
-            }
-            Objective::DoorState(door_id, door_state) => {
-                if state.door_states[*door_id] != *door_state {
-                    distance += 1.0;
-                }
-            }
-        }
+// A posted JSON body, or where it stopped making sense. Parsed here
+// rather than by the Json guard so that the handler can report the error,
+// even one with reading the body.
+struct Posted<T>(Result<T, Diagnostic>);
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for Posted<T> {
+    type Error = Infallible;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+
+        let problem = |code, message| Diagnostic {
+            code,
+            message,
+            path: ".".to_string(),
+        };
+
+        let posted = match data.open(limit).into_string().await {
+            Ok(body) if body.is_complete() => from_json(&body),
+            Ok(_) => Err(problem(
+                ErrorCode::PayloadTooLarge,
+                format!("the body is larger than {}", limit),
+            )),
+            Err(error) => Err(problem(
+                ErrorCode::MalformedJson,
+                format!("the body could not be read: {}", error),
+            )),
+        };
+        Outcome::Success(Posted(posted))
     }
+}
 
-    distance
+/// The body of a 4xx response: the first problem found, and any others
+/// after it
+#[derive(Serialize)]
+struct ErrorBody {
+    #[serde(flatten)]
+    error: Diagnostic,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    more: Vec<Diagnostic>,
 }
 
-#[derive(Deserialize, Debug)]
-enum Objective {
-    ActorPos(PosId),
-    DoorState(DoorId, DoorState),
+#[derive(Responder)]
+enum Rejection {
+    #[response(status = 400)]
+    Malformed(Json<ErrorBody>),
+    #[response(status = 413)]
+    TooLarge(Json<ErrorBody>),
+    #[response(status = 422)]
+    Invalid(Json<ErrorBody>),
+    #[response(status = 404)]
+    NotFound(Json<ErrorBody>),
+    #[response(status = 503)]
+    Busy(Json<ErrorBody>),
+    Failed(Status),
 }
 
-#[derive(Deserialize, Debug)]
-struct Scenario {
-    world: World,
-    state: State,
-    objectives: Vec<Objective>,
+// Reject a request over its problems, of which there is at least one
+fn reject(mut problems: Vec<Diagnostic>) -> Rejection {
+    let error = problems.remove(0);
+    let code = error.code;
+    let body = Json(ErrorBody {
+        error,
+        more: problems,
+    });
+
+    match code {
+        ErrorCode::MalformedJson => Rejection::Malformed(body),
+        ErrorCode::PayloadTooLarge => Rejection::TooLarge(body),
+        _ => Rejection::Invalid(body),
+    }
+}
+
+// The posted scenario, checked along with its action schemas and held to
+// the server's limits
+fn accept(scenario: Posted<Scenario>, config: &PlannerConfig) -> Result<Scenario, Rejection> {
+    let mut scenario = scenario.0.map_err(|error| reject(vec![error]))?;
+
+    let mut problems = validate(&scenario);
+    if problems.is_empty() {
+        problems = scenario.domain.check(&scenario.world, &scenario.state);
+    }
+    if !problems.is_empty() {
+        return Err(reject(problems));
+    }
+
+    scenario.limits = scenario.limits.tightest(&config.planner_limits);
+    Ok(scenario)
 }
 
-#[derive(Debug, Serialize)]
-struct PlannerResult {
-    actions: Vec<Action>,
-    success: bool,
+#[post("/", data = "<scenario>")]
+async fn index(
+    scenario: Posted<Scenario>,
+    config: &rocket::State<PlannerConfig>,
+) -> Result<Json<PlannerResult>, Rejection> {
+    let mut scenario = accept(scenario, config)?;
+    let cancel = CancelToken::new();
+    scenario.limits.cancel = Some(cancel.clone());
+
+    let _guard = CancelOnDrop(cancel);
+    let planner_result = spawn_blocking(move || run_scenario(scenario))
+        .await
+        .map_err(|_| Rejection::Failed(Status::InternalServerError))?;
+    Ok(Json(planner_result))
 }
 
-fn run_scenario_from_file(scenario_filename: &str) {
-    let data = read_to_string(scenario_filename).unwrap();
-    let scenario: Scenario = serde_json::from_str(&data).unwrap();
-    let result = run_scenario(scenario);
-    dbg!(result);
+type JobId = u64;
+
+/// Where a planning job has got to
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Running,
+    /// The planner finished, whether or not it found a plan
+    Done,
+    /// The planner crashed
+    Failed,
 }
 
-fn run_scenario(scenario: Scenario) -> PlannerResult {
-    let s0 = scenario.state;
-    let w = scenario.world;
-    let o = scenario.objectives;
+struct Job {
+    status: JobStatus,
+    cancel: CancelToken,
+    progress: PlanProgress,
+    include_states: bool,
+    result: Option<PlannerResult>,
+    /// When a finished job is forgotten
+    expires: Option<Instant>,
+}
 
-    let result = a_star(&s0, &|s| heuristic(s, &o), &|s: &State| {
-        get_neighbors(s, &w)
-    });
+/// What GET /jobs/<id> answers
+#[derive(Serialize)]
+struct JobReport {
+    id: JobId,
+    status: JobStatus,
+    /// How far the search has got, while it runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<Progress>,
+    /// While the search runs, the path to the state that looks closest to
+    /// the goal. An anytime search's is the best plan found so far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partial_plan: Option<Vec<PlanStep>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<PlannerResult>,
+}
+
+/// Planning jobs by id. A job is kept after it finishes, until it is
+/// deleted or its time to live runs out.
+#[derive(Default, Clone)]
+struct Jobs {
+    next_id: Arc<AtomicU64>,
+    jobs: Arc<Mutex<HashMap<JobId, Job>>>,
+}
+
+impl Jobs {
+    // The jobs, less any that have expired
+    fn lock(&self) -> MutexGuard<'_, HashMap<JobId, Job>> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let now = Instant::now();
+        jobs.retain(|_, job| job.expires.is_none_or(|expires| expires > now));
+        jobs
+    }
+
+    // Add a running job, unless `max_running` already are
+    fn start(
+        &self,
+        cancel: CancelToken,
+        progress: PlanProgress,
+        include_states: bool,
+        max_running: usize,
+    ) -> Option<JobId> {
+        let mut jobs = self.lock();
+        let running = jobs
+            .values()
+            .filter(|job| matches!(job.status, JobStatus::Running))
+            .count();
+        if running >= max_running {
+            return None;
+        }
 
-    let planner_result = match result {
-        Ok(result) => {
-            let actions = result.into_iter().map(|(_, action)| action).collect();
-            PlannerResult {
-                actions,
-                success: true,
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            status: JobStatus::Running,
+            cancel,
+            progress,
+            include_states,
+            result: None,
+            expires: None,
+        };
+
+        jobs.insert(id, job);
+        Some(id)
+    }
+
+    // Record the planner's result, or None if it crashed, and keep it for
+    // `ttl`. Does nothing if the job was deleted meanwhile.
+    fn finish(&self, id: JobId, result: Option<PlannerResult>, ttl: Duration) {
+        if let Some(job) = self.lock().get_mut(&id) {
+            job.expires = Some(Instant::now() + ttl);
+            job.status = match result {
+                Some(_) => JobStatus::Done,
+                None => JobStatus::Failed,
+            };
+            job.result = result;
+        }
+    }
+
+    fn report(&self, id: JobId) -> Option<JobReport> {
+        let jobs = self.lock();
+        let job = jobs.get(&id)?;
+
+        let (progress, partial_plan) = match job.status {
+            JobStatus::Running => {
+                let (progress, partial_plan) = job.progress.latest_with_plan(job.include_states);
+                (Some(progress), partial_plan)
+            }
+            JobStatus::Done | JobStatus::Failed => (None, None),
+        };
+
+        Some(JobReport {
+            id,
+            status: job.status,
+            progress,
+            partial_plan,
+            result: job.result.clone(),
+        })
+    }
+
+    // Cancel a running job, which is kept until its planner stops, or
+    // forget a finished one. Returns the job's status before, or None if
+    // there is no such job.
+    fn cancel(&self, id: JobId) -> Option<JobStatus> {
+        let mut jobs = self.lock();
+        let job = jobs.get(&id)?;
+        let status = job.status;
+
+        match status {
+            JobStatus::Running => job.cancel.cancel(),
+            JobStatus::Done | JobStatus::Failed => {
+                jobs.remove(&id);
             }
         }
-        Err(_) => PlannerResult {
-            actions: vec![],
-            success: false,
-        },
+        Some(status)
+    }
+}
+
+/// Start planning the scenario in the background. Answers with the job,
+/// whose id is for GET and DELETE /jobs/<id>, or 503 if the server is
+/// already running as many jobs as it allows.
+#[post("/jobs", data = "<scenario>")]
+async fn create_job(
+    scenario: Posted<Scenario>,
+    config: &rocket::State<PlannerConfig>,
+    jobs: &rocket::State<Jobs>,
+) -> Result<(Status, Json<JobReport>), Rejection> {
+    let mut scenario = accept(scenario, config)?;
+    let cancel = CancelToken::new();
+    let progress = PlanProgress::new();
+    scenario.limits.cancel = Some(cancel.clone());
+    scenario.limits.progress = Some(progress.clone());
+
+    let jobs = jobs.inner().clone();
+    let include_states = scenario.include_states;
+    let id = jobs
+        .start(cancel, progress, include_states, config.max_running_jobs)
+        .ok_or_else(|| too_many_jobs(config.max_running_jobs))?;
+
+    let finished = jobs.clone();
+    let ttl = Duration::from_secs(config.finished_job_ttl_secs);
+    rocket::tokio::spawn(async move {
+        let result = spawn_blocking(move || run_scenario(scenario)).await;
+        finished.finish(id, result.ok(), ttl);
+    });
+
+    let report = JobReport {
+        id,
+        status: JobStatus::Running,
+        progress: Some(Progress::default()),
+        partial_plan: None,
+        result: None,
     };
+    Ok((Status::Accepted, Json(report)))
+}
 
-    planner_result
+fn too_many_jobs(max_running: usize) -> Rejection {
+    Rejection::Busy(Json(ErrorBody {
+        error: Diagnostic {
+            code: ErrorCode::TooManyJobs,
+            message: format!("{} jobs are already running", max_running),
+            path: ".".to_string(),
+        },
+        more: vec![],
+    }))
 }
 
-#[post("/", data = "<scenario>")]
-fn index(scenario: Json<Scenario>) -> Json<PlannerResult> {
-    let scenario = scenario.into_inner();
-    let planner_result = run_scenario(scenario);
-    Json(planner_result)
+// The answer for a job that doesn't exist, or has expired
+fn unknown_job(id: JobId) -> Rejection {
+    Rejection::NotFound(Json(ErrorBody {
+        error: Diagnostic {
+            code: ErrorCode::UnknownJob,
+            message: format!("job {} does not exist", id),
+            path: "id".to_string(),
+        },
+        more: vec![],
+    }))
+}
+
+#[get("/jobs/<id>")]
+fn get_job(id: JobId, jobs: &rocket::State<Jobs>) -> Result<Json<JobReport>, Rejection> {
+    jobs.report(id).map(Json).ok_or_else(|| unknown_job(id))
+}
+
+/// Cancel the job if it is still running. It is kept until the planner
+/// stops, and its result then has the best plan found so far, which an
+/// anytime search may have before it finishes. A finished job is
+/// forgotten.
+#[delete("/jobs/<id>")]
+fn delete_job(id: JobId, jobs: &rocket::State<Jobs>) -> Result<Status, Rejection> {
+    match jobs.cancel(id) {
+        Some(JobStatus::Running) => Ok(Status::Accepted),
+        Some(JobStatus::Done | JobStatus::Failed) => Ok(Status::NoContent),
+        None => Err(unknown_job(id)),
+    }
+}
+
+/// Plan many scenarios at once, in parallel. Takes a JSON array of
+/// scenarios, or an object with one world and the problems to plan in it.
+/// Answers with a PlannerResult for each, in order; a scenario that is
+/// invalid or has no plan fails on its own. An invalid one's result lists
+/// its diagnostics.
+#[post("/batch", data = "<batch>")]
+async fn batch(
+    batch: Posted<Batch>,
+    config: &rocket::State<PlannerConfig>,
+) -> Result<Json<Vec<PlannerResult>>, Rejection> {
+    let batch = batch.0.map_err(|error| reject(vec![error]))?;
+    let cancel = CancelToken::new();
+
+    let mut scenarios = batch.into_scenarios();
+    for scenario in &mut scenarios {
+        scenario.limits = scenario.limits.tightest(&config.planner_limits);
+        scenario.limits.cancel = Some(cancel.clone());
+    }
+
+    let _guard = CancelOnDrop(cancel);
+    let results = spawn_blocking(move || run_batch(scenarios))
+        .await
+        .map_err(|_| Rejection::Failed(Status::InternalServerError))?;
+    Ok(Json(results))
+}
+
+// Time between progress events
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A `progress` event of the stream route
+#[derive(Serialize)]
+struct ProgressEvent {
+    #[serde(flatten)]
+    progress: Progress,
+    /// The path to the state that looks closest to the goal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    partial_plan: Option<Vec<PlanStep>>,
+}
+
+/// Plan the scenario, streaming server-sent events: a `progress` event
+/// every so often while the search runs, then a `result` event with the
+/// PlannerResult. The search is cancelled if the client goes away.
+#[post("/stream", data = "<scenario>")]
+async fn stream(
+    scenario: Posted<Scenario>,
+    config: &rocket::State<PlannerConfig>,
+) -> Result<EventStream![], Rejection> {
+    let mut scenario = accept(scenario, config)?;
+    let cancel = CancelToken::new();
+    let progress = PlanProgress::new();
+    scenario.limits.cancel = Some(cancel.clone());
+    scenario.limits.progress = Some(progress.clone());
+
+    let include_states = scenario.include_states;
+    let mut planning = spawn_blocking(move || run_scenario(scenario));
+
+    Ok(EventStream! {
+        let _guard = CancelOnDrop(cancel);
+
+        loop {
+            match timeout(PROGRESS_EVENT_INTERVAL, &mut planning).await {
+                Ok(Ok(result)) => {
+                    yield Event::json(&result).event("result");
+                    break;
+                }
+                Ok(Err(_)) => {
+                    yield Event::data("the planner crashed").event("error");
+                    break;
+                }
+                Err(_) => {
+                    let (progress, partial_plan) = progress.latest_with_plan(include_states);
+                    let event = ProgressEvent {
+                        progress,
+                        partial_plan,
+                    };
+                    yield Event::json(&event).event("progress");
+                }
+            }
+        }
+    })
 }
 
 fn rocket() -> Rocket<Build> {
-    rocket::build().mount("/", routes![index])
+    server(rocket::Config::figment())
+}
+
+fn server(figment: Figment) -> Rocket<Build> {
+    rocket::custom(figment)
+        .mount(
+            "/",
+            routes![index, create_job, get_job, delete_job, stream, batch],
+        )
+        .manage(Jobs::default())
+        .attach(AdHoc::config::<PlannerConfig>())
 }
 
 #[rocket::main]
 async fn main() {
     let input_file = std::env::args().nth(1);
+    let domain_file = std::env::args().nth(2);
 
     match input_file {
-        Some(x) => run_scenario_from_file(&x),
+        Some(x) => {
+            if let Err(error) = run_scenario_from_file(&x, domain_file.as_deref()) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
         None => {
             let _ = rocket().launch().await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{rocket, server, JobStatus, Jobs, PlanProgress};
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+    use rustplan::a_star::{CancelToken, Progress, ReportProgress, Step};
+    use rustplan::actions::Action;
+    use rustplan::{run_scenario, State};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    fn post(body: &str) -> (Status, Value) {
+        post_to(&Client::tracked(rocket()).unwrap(), "/", body)
+    }
+
+    fn post_to(client: &Client, route: &str, body: &str) -> (Status, Value) {
+        let response = client
+            .post(route.to_string())
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch();
+        let status = response.status();
+        let body = response.into_string().unwrap();
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    // Two rooms joined by a locked door, with an action schema to unlock it
+    fn scenario(actor_pos: usize, effects: Value) -> String {
+        json!({
+            "world": {
+                "pos_move_groups": [1, 1, 1, 2, 2],
+                "door_side_a": [2],
+                "door_side_b": [3],
+            },
+            "state": {
+                "actors": [{"pos": actor_pos}],
+                "door_states": ["Locked"],
+                "flags": [false],
+            },
+            "objectives": [{"ActorPos": [0, 4]}],
+            "domain": {
+                "action_schemas": [{
+                    "name": "PressButton",
+                    "preconditions": [{"ActorPos": 1}, {"Flag": [0, false]}],
+                    "effects": effects,
+                }],
+            },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn malformed_json_is_a_bad_request() {
+        let (status, body) = post("{\"world\": ");
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["code"], "malformed_json");
+    }
+
+    #[test]
+    fn an_unreadable_body_is_a_json_bad_request() {
+        let client = Client::tracked(rocket()).unwrap();
+        let response = client
+            .post("/")
+            .header(ContentType::JSON)
+            .body([b'{', 0xff, 0xfe, b'}'])
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let body: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["code"], "malformed_json");
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("the body could not be read"));
+    }
+
+    #[test]
+    fn an_oversized_body_is_a_json_payload_too_large() {
+        let figment = rocket::Config::figment().merge(("limits.json", 64));
+        let client = Client::tracked(server(figment)).unwrap();
+
+        for route in ["/", "/jobs", "/stream", "/batch"] {
+            let body = json!({"padding": "x".repeat(100)}).to_string();
+            let (status, body) = post_to(&client, route, &body);
+            assert_eq!(status, Status::PayloadTooLarge, "{}", route);
+            assert_eq!(body["code"], "payload_too_large");
+            assert_eq!(body["path"], ".");
+        }
+    }
+
+    #[test]
+    fn an_invalid_scenario_is_unprocessable() {
+        let effects = json!([{"Flag": [0, true]}, {"DoorState": [0, "Closed"]}]);
+        let (status, body) = post(&scenario(9, effects));
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["code"], "unknown_id");
+        assert_eq!(body["path"], "state.actors[0].pos");
+    }
+
+    #[test]
+    fn an_invalid_domain_is_unprocessable_with_each_problem() {
+        let effects = json!([{"DoorState": [3, "Closed"]}, {"Flag": ["?flag", true]}]);
+        let (status, body) = post(&scenario(0, effects));
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(body["code"], "unknown_id");
+        assert_eq!(body["path"], "domain.action_schemas[0].effects[0]");
+        assert_eq!(body["more"][0]["code"], "invalid_domain");
+        assert_eq!(
+            body["more"][0]["path"],
+            "domain.action_schemas[0].effects[1]"
+        );
+    }
+
+    #[test]
+    fn every_route_rejects_an_invalid_domain_before_planning() {
+        let client = Client::tracked(rocket()).unwrap();
+        let effects = json!([{"Flag": ["?flag", true]}]);
+
+        for route in ["/", "/jobs", "/stream"] {
+            let (status, body) = post_to(&client, route, &scenario(0, effects.clone()));
+            assert_eq!(status, Status::UnprocessableEntity, "{}", route);
+            assert_eq!(body["code"], "invalid_domain");
+            assert_eq!(body["path"], "domain.action_schemas[0].effects[0]");
+        }
+    }
+
+    #[test]
+    fn jobs_over_the_limit_are_refused() {
+        let figment = rocket::Config::figment().merge(("max_running_jobs", 0));
+        let client = Client::tracked(server(figment)).unwrap();
+        let effects = json!([{"Flag": [0, true]}, {"DoorState": [0, "Closed"]}]);
+
+        let (status, body) = post_to(&client, "/jobs", &scenario(0, effects));
+        assert_eq!(status, Status::ServiceUnavailable);
+        assert_eq!(body["code"], "too_many_jobs");
+
+        let jobs = Jobs::default();
+        let first = jobs.start(CancelToken::new(), PlanProgress::new(), false, 1);
+        assert!(first.is_some());
+        assert!(jobs
+            .start(CancelToken::new(), PlanProgress::new(), false, 1)
+            .is_none());
+
+        // A finished job no longer counts
+        jobs.finish(first.unwrap(), None, Duration::from_secs(600));
+        assert!(jobs
+            .start(CancelToken::new(), PlanProgress::new(), false, 1)
+            .is_some());
+    }
+
+    #[test]
+    fn a_scenario_without_a_plan_is_ok_but_unsuccessful() {
+        // Without the built-in actions nothing moves the actor
+        let effects = json!([{"Flag": [0, true]}]);
+        let mut scenario: Value = serde_json::from_str(&scenario(0, effects)).unwrap();
+        scenario["domain"]["builtin_actions"] = json!(false);
+        let (status, body) = post(&scenario.to_string());
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["failure_reason"]["code"], "unreachable");
+    }
+
+    #[test]
+    fn a_plan_is_found() {
+        let effects = json!([{"Flag": [0, true]}, {"DoorState": [0, "Closed"]}]);
+        let (status, body) = post(&scenario(0, effects));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body["success"], true);
+    }
+
+    #[test]
+    fn unknown_jobs_are_json_not_found() {
+        let client = Client::tracked(rocket()).unwrap();
+
+        for response in [
+            client.get("/jobs/12345").dispatch(),
+            client.delete("/jobs/12345").dispatch(),
+        ] {
+            assert_eq!(response.status(), Status::NotFound);
+            assert_eq!(response.content_type(), Some(ContentType::JSON));
+            let body: Value = serde_json::from_str(&response.into_string().unwrap()).unwrap();
+            assert_eq!(body["code"], "unknown_job");
+            assert_eq!(body["path"], "id");
+        }
+    }
+
+    #[test]
+    fn finished_jobs_expire() {
+        let jobs = Jobs::default();
+        let kept = jobs
+            .start(CancelToken::new(), PlanProgress::new(), false, 10)
+            .unwrap();
+        let expired = jobs
+            .start(CancelToken::new(), PlanProgress::new(), false, 10)
+            .unwrap();
+        let running = jobs
+            .start(CancelToken::new(), PlanProgress::new(), false, 10)
+            .unwrap();
+
+        jobs.finish(kept, None, Duration::from_secs(600));
+        jobs.finish(expired, None, Duration::ZERO);
+
+        assert!(matches!(
+            jobs.report(kept).unwrap().status,
+            JobStatus::Failed
+        ));
+        assert!(jobs.report(expired).is_none());
+        assert!(matches!(
+            jobs.report(running).unwrap().status,
+            JobStatus::Running
+        ));
+    }
+
+    #[test]
+    fn running_jobs_report_their_partial_plan() {
+        let progress = PlanProgress::new();
+        let jobs = Jobs::default();
+        let id = jobs
+            .start(CancelToken::new(), progress.clone(), false, 10)
+            .unwrap();
+
+        let state: State = serde_json::from_value(json!({
+            "actors": [{"pos": 1}],
+            "door_states": [],
+        }))
+        .unwrap();
+        progress.report(Progress::default(), || {
+            Some(vec![Step {
+                action: Action::Move { actor: 0, to: 1 },
+                cost: 1.0,
+                g: 1.0,
+                state,
+            }])
+        });
+
+        let report = serde_json::to_value(jobs.report(id).unwrap()).unwrap();
+        assert_eq!(report["status"], "running");
+        assert_eq!(report["partial_plan"][0]["Move"]["to"], 1);
+        assert!(report["partial_plan"][0].get("state").is_none());
+    }
+
+    #[test]
+    fn cancelled_jobs_keep_their_result() {
+        let cancel = CancelToken::new();
+        let jobs = Jobs::default();
+        let id = jobs
+            .start(cancel.clone(), PlanProgress::new(), false, 10)
+            .unwrap();
+
+        assert!(matches!(jobs.cancel(id), Some(JobStatus::Running)));
+        assert!(cancel.is_cancelled());
+        assert!(jobs.report(id).is_some());
+
+        let effects = json!([{"Flag": [0, true]}, {"DoorState": [0, "Closed"]}]);
+        let scenario = serde_json::from_str(&scenario(0, effects)).unwrap();
+        jobs.finish(id, Some(run_scenario(scenario)), Duration::from_secs(600));
+        let report = serde_json::to_value(jobs.report(id).unwrap()).unwrap();
+        assert_eq!(report["status"], "done");
+        assert_eq!(report["result"]["success"], true);
+
+        // Once it has finished, it is forgotten
+        assert!(matches!(jobs.cancel(id), Some(JobStatus::Done)));
+        assert!(jobs.report(id).is_none());
+    }
+}
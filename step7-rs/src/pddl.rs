@@ -0,0 +1,956 @@
+use crate::a_star::{Cost, PlanError};
+use crate::strips::{FactId, FactSet, Operator, Task};
+use std::collections::{HashMap, HashSet};
+
+// The STRIPS subset of PDDL, plus typing, negative preconditions, equality
+// and action costs. Names are case-insensitive and are lowercased while
+// reading.
+
+#[derive(Debug, Clone)]
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom) => Some(atom),
+            Sexp::List(_) => None,
+        }
+    }
+
+    fn list(&self) -> Result<&[Sexp], String> {
+        match self {
+            Sexp::List(items) => Ok(items),
+            Sexp::Atom(atom) => Err(format!("expected a list, found {}", atom)),
+        }
+    }
+}
+
+fn parse_sexp(src: &str) -> Result<Sexp, String> {
+    let mut tokens = vec![];
+    for line in src.lines() {
+        let code = line.split(';').next().unwrap_or("");
+        let spaced = code.replace('(', " ( ").replace(')', " ) ");
+        tokens.extend(spaced.split_whitespace().map(str::to_lowercase));
+    }
+
+    let mut stack: Vec<Vec<Sexp>> = vec![vec![]];
+    for token in tokens {
+        match token.as_str() {
+            "(" => stack.push(vec![]),
+            ")" => {
+                let list = stack.pop().filter(|_| !stack.is_empty());
+                let list = list.ok_or("unbalanced ')'")?;
+                stack.last_mut().unwrap().push(Sexp::List(list));
+            }
+            _ => stack.last_mut().unwrap().push(Sexp::Atom(token)),
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err("unbalanced '('".to_owned());
+    }
+
+    let mut top = stack.pop().unwrap();
+    match top.len() {
+        1 => Ok(top.pop().unwrap()),
+        0 => Err("empty file".to_owned()),
+        _ => Err("more than one top-level expression".to_owned()),
+    }
+}
+
+// `a b - t c` becomes [(a, t), (b, t), (c, object)]
+fn typed_list(items: &[Sexp]) -> Result<Vec<(String, String)>, String> {
+    let mut typed = vec![];
+    let mut pending = vec![];
+    let mut items = items.iter();
+
+    while let Some(item) = items.next() {
+        let name = item.atom().ok_or("unsupported type expression")?;
+        if name == "-" {
+            let kind = items.next().and_then(Sexp::atom);
+            let kind = kind.ok_or("expected a type name after '-'")?;
+            typed.extend(pending.drain(..).map(|n| (n, kind.to_owned())));
+        } else {
+            pending.push(name.to_owned());
+        }
+    }
+
+    typed.extend(pending.into_iter().map(|n| (n, "object".to_owned())));
+    Ok(typed)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Atom {
+    predicate: String,
+    args: Vec<String>,
+}
+
+fn atom(sexp: &Sexp) -> Result<Atom, String> {
+    let items = sexp.list()?;
+    let mut names = items.iter().map(|item| {
+        item.atom()
+            .map(str::to_owned)
+            .ok_or_else(|| "nested terms are not supported".to_owned())
+    });
+
+    let predicate = names.next().ok_or("empty atom")??;
+    Ok(Atom {
+        predicate,
+        args: names.collect::<Result<_, _>>()?,
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Pos(Atom),
+    Neg(Atom),
+    /// Two terms are equal, or differ when false
+    Eq(String, String, bool),
+}
+
+impl Literal {
+    fn args(&self) -> Vec<&String> {
+        match self {
+            Literal::Pos(atom) | Literal::Neg(atom) => atom.args.iter().collect(),
+            Literal::Eq(a, b, _) => vec![a, b],
+        }
+    }
+}
+
+fn head(items: &[Sexp]) -> Option<&str> {
+    items.first().and_then(Sexp::atom)
+}
+
+fn literal(sexp: &Sexp) -> Result<Literal, String> {
+    let items = sexp.list()?;
+
+    match head(items) {
+        Some("not") if items.len() == 2 => match literal(&items[1])? {
+            Literal::Pos(atom) => Ok(Literal::Neg(atom)),
+            Literal::Eq(a, b, true) => Ok(Literal::Eq(a, b, false)),
+            _ => Err("only atoms and equalities can be negated".to_owned()),
+        },
+        Some("=") => {
+            let atom = atom(sexp)?;
+            match atom.args.as_slice() {
+                [a, b] => Ok(Literal::Eq(a.clone(), b.clone(), true)),
+                _ => Err("= takes two terms".to_owned()),
+            }
+        }
+        Some(
+            keyword @ ("and" | "or" | "imply" | "exists" | "forall" | "when" | "not" | "either"),
+        ) => Err(format!("{} is not supported here", keyword)),
+        _ => Ok(Literal::Pos(atom(sexp)?)),
+    }
+}
+
+// A conjunction of literals: `()`, `(and ...)` or a single literal
+fn conjunction(sexp: &Sexp) -> Result<Vec<Literal>, String> {
+    let items = sexp.list()?;
+
+    match head(items) {
+        None if items.is_empty() => Ok(vec![]),
+        Some("and") => items[1..].iter().map(literal).collect(),
+        _ => Ok(vec![literal(sexp)?]),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CostExpr {
+    Constant(Cost),
+    Function(Atom),
+}
+
+#[derive(Debug, Clone)]
+enum Effect {
+    Add(Atom),
+    Del(Atom),
+    Increase(CostExpr),
+}
+
+fn effect(sexp: &Sexp) -> Result<Effect, String> {
+    let items = sexp.list()?;
+
+    if head(items) != Some("increase") {
+        return match literal(sexp)? {
+            Literal::Pos(atom) => Ok(Effect::Add(atom)),
+            Literal::Neg(atom) => Ok(Effect::Del(atom)),
+            Literal::Eq(..) => Err("= cannot be an effect".to_owned()),
+        };
+    }
+
+    let is_total_cost = items
+        .get(1)
+        .map(atom)
+        .transpose()?
+        .is_some_and(|target| target.predicate == "total-cost" && target.args.is_empty());
+    if items.len() != 3 || !is_total_cost {
+        return Err("only (increase (total-cost) ...) is supported".to_owned());
+    }
+
+    match &items[2] {
+        Sexp::Atom(number) => number
+            .parse()
+            .map(|cost| Effect::Increase(CostExpr::Constant(cost)))
+            .map_err(|_| format!("{} is not a number", number)),
+        function => Ok(Effect::Increase(CostExpr::Function(atom(function)?))),
+    }
+}
+
+fn effects(sexp: &Sexp) -> Result<Vec<Effect>, String> {
+    let items = sexp.list()?;
+
+    match head(items) {
+        None if items.is_empty() => Ok(vec![]),
+        Some("and") => items[1..].iter().map(effect).collect(),
+        _ => Ok(vec![effect(sexp)?]),
+    }
+}
+
+#[derive(Debug)]
+struct ActionDef {
+    name: String,
+    parameters: Vec<(String, String)>,
+    precondition: Vec<Literal>,
+    effect: Vec<Effect>,
+}
+
+#[derive(Debug)]
+struct DomainDef {
+    name: String,
+    // Each type with its parent
+    types: HashMap<String, String>,
+    constants: Vec<(String, String)>,
+    actions: Vec<ActionDef>,
+}
+
+#[derive(Debug)]
+struct ProblemDef {
+    domain: String,
+    objects: Vec<(String, String)>,
+    init: HashSet<Atom>,
+    numbers: HashMap<Atom, Cost>,
+    goal: Vec<Literal>,
+}
+
+// Split `(define (kind name) (:section ...) ...)` into its name and
+// sections
+fn define<'a>(sexp: &'a Sexp, kind: &str) -> Result<(String, Vec<&'a [Sexp]>), String> {
+    let items = sexp.list()?;
+    if head(items) != Some("define") {
+        return Err("expected (define ...)".to_owned());
+    }
+
+    let name = items.get(1).map(Sexp::list).transpose()?;
+    let name = match name {
+        Some([Sexp::Atom(k), Sexp::Atom(name)]) if k == kind => name.clone(),
+        _ => return Err(format!("expected ({} <name>)", kind)),
+    };
+
+    let sections = items[2..]
+        .iter()
+        .map(Sexp::list)
+        .collect::<Result<_, _>>()?;
+    Ok((name, sections))
+}
+
+fn parse_action(items: &[Sexp]) -> Result<ActionDef, String> {
+    let name = items
+        .get(1)
+        .and_then(Sexp::atom)
+        .ok_or("action without a name")?;
+    let mut action = ActionDef {
+        name: name.to_owned(),
+        parameters: vec![],
+        precondition: vec![],
+        effect: vec![],
+    };
+
+    let mut fields = items[2..].iter();
+    while let Some(field) = fields.next() {
+        let value = fields
+            .next()
+            .ok_or_else(|| format!("{}: missing value after {:?}", name, field))?;
+        let result = match field.atom() {
+            Some(":parameters") => typed_list(value.list()?).map(|p| action.parameters = p),
+            Some(":precondition") => conjunction(value).map(|p| action.precondition = p),
+            Some(":effect") => effects(value).map(|e| action.effect = e),
+            _ => Err(format!("unknown field {:?}", field)),
+        };
+        result.map_err(|reason| format!("action {}: {}", name, reason))?;
+    }
+
+    Ok(action)
+}
+
+fn parse_domain(src: &str) -> Result<DomainDef, String> {
+    let sexp = parse_sexp(src)?;
+    let (name, sections) = define(&sexp, "domain")?;
+    let mut domain = DomainDef {
+        name,
+        types: HashMap::new(),
+        constants: vec![],
+        actions: vec![],
+    };
+
+    for section in sections {
+        match head(section) {
+            Some(":types") => {
+                for (kind, parent) in typed_list(&section[1..])? {
+                    domain.types.insert(kind, parent);
+                }
+            }
+            Some(":constants") => domain.constants = typed_list(&section[1..])?,
+            Some(":action") => domain.actions.push(parse_action(section)?),
+            // Predicate and function declarations add nothing the rest of
+            // the file doesn't already say
+            Some(":requirements") | Some(":predicates") | Some(":functions") => {}
+            other => return Err(format!("unsupported domain section {:?}", other)),
+        }
+    }
+
+    Ok(domain)
+}
+
+fn parse_problem(src: &str) -> Result<ProblemDef, String> {
+    let sexp = parse_sexp(src)?;
+    let (_, sections) = define(&sexp, "problem")?;
+    let mut problem = ProblemDef {
+        domain: String::new(),
+        objects: vec![],
+        init: HashSet::new(),
+        numbers: HashMap::new(),
+        goal: vec![],
+    };
+
+    for section in sections {
+        match head(section) {
+            Some(":domain") => {
+                let name = section
+                    .get(1)
+                    .and_then(Sexp::atom)
+                    .ok_or("missing domain name")?;
+                problem.domain = name.to_owned();
+            }
+            Some(":objects") => problem.objects = typed_list(&section[1..])?,
+            Some(":init") => {
+                for fact in &section[1..] {
+                    let items = fact.list()?;
+                    if head(items) == Some("=") && items.len() == 3 {
+                        let value = items[2].atom().and_then(|n| n.parse().ok());
+                        let value = value.ok_or("expected a number in (= ...)")?;
+                        problem.numbers.insert(atom(&items[1])?, value);
+                    } else {
+                        problem.init.insert(atom(fact)?);
+                    }
+                }
+            }
+            Some(":goal") => {
+                let goal = section.get(1).ok_or("empty goal")?;
+                problem.goal = conjunction(goal)?;
+            }
+            // Action costs are always minimised
+            Some(":metric") | Some(":requirements") => {}
+            other => return Err(format!("unsupported problem section {:?}", other)),
+        }
+    }
+
+    Ok(problem)
+}
+
+// Interns ground atoms as facts
+#[derive(Default)]
+struct Facts {
+    ids: HashMap<Atom, FactId>,
+    names: Vec<String>,
+}
+
+impl Facts {
+    fn id(&mut self, atom: Atom) -> FactId {
+        let names = &mut self.names;
+        *self.ids.entry(atom).or_insert_with_key(|atom| {
+            let mut name = format!("({}", atom.predicate);
+            for arg in &atom.args {
+                name.push(' ');
+                name.push_str(arg);
+            }
+            name.push(')');
+            names.push(name);
+            names.len() - 1
+        })
+    }
+}
+
+struct Grounder<'a> {
+    domain: &'a DomainDef,
+    problem: &'a ProblemDef,
+    // Objects and constants with their types
+    objects: Vec<(String, String)>,
+    // Predicates no action changes, which are decided while grounding
+    statics: HashSet<String>,
+    uses_costs: bool,
+    facts: Facts,
+}
+
+impl<'a> Grounder<'a> {
+    fn is_subtype(&self, kind: &str, ancestor: &str) -> bool {
+        let mut kind = kind;
+        let mut seen = 0;
+
+        loop {
+            if kind == ancestor || ancestor == "object" {
+                return true;
+            }
+            match self.domain.types.get(kind) {
+                // A bound on the walk guards against cyclic declarations
+                Some(parent) if seen <= self.domain.types.len() => kind = parent,
+                _ => return false,
+            }
+            seen += 1;
+        }
+    }
+
+    fn is_type(&self, kind: &str) -> bool {
+        kind == "object"
+            || self.domain.types.contains_key(kind)
+            || self.domain.types.values().any(|parent| parent == kind)
+    }
+
+    fn check_objects(&self) -> Result<(), String> {
+        for (i, (name, kind)) in self.objects.iter().enumerate() {
+            if self.objects[..i].iter().any(|(n, _)| n == name) {
+                return Err(format!("object {} is declared twice", name));
+            }
+            if !self.is_type(kind) {
+                return Err(format!("object {} has unknown type {}", name, kind));
+            }
+        }
+        Ok(())
+    }
+
+    // A term as an object name, given the bound parameters
+    fn resolve(
+        &self,
+        term: &str,
+        parameters: &[(String, String)],
+        args: &[usize],
+    ) -> Result<String, String> {
+        if term.starts_with('?') {
+            let i = parameters
+                .iter()
+                .position(|(name, _)| name == term)
+                .ok_or_else(|| format!("unknown parameter {}", term))?;
+            Ok(self.objects[args[i]].0.clone())
+        } else if self.objects.iter().any(|(name, _)| name == term) {
+            Ok(term.to_owned())
+        } else {
+            Err(format!("unknown object {}", term))
+        }
+    }
+
+    fn ground_atom(
+        &self,
+        atom: &Atom,
+        parameters: &[(String, String)],
+        args: &[usize],
+    ) -> Result<Atom, String> {
+        Ok(Atom {
+            predicate: atom.predicate.clone(),
+            args: atom
+                .args
+                .iter()
+                .map(|term| self.resolve(term, parameters, args))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    // Whether a literal that needs no fact holds for the binding
+    fn static_holds(
+        &self,
+        literal: &Literal,
+        parameters: &[(String, String)],
+        args: &[usize],
+    ) -> Result<bool, String> {
+        Ok(match literal {
+            Literal::Pos(atom) => self
+                .problem
+                .init
+                .contains(&self.ground_atom(atom, parameters, args)?),
+            Literal::Neg(atom) => !self
+                .problem
+                .init
+                .contains(&self.ground_atom(atom, parameters, args)?),
+            Literal::Eq(a, b, equal) => {
+                (self.resolve(a, parameters, args)? == self.resolve(b, parameters, args)?) == *equal
+            }
+        })
+    }
+
+    fn is_static(&self, literal: &Literal) -> bool {
+        match literal {
+            Literal::Pos(atom) | Literal::Neg(atom) => self.statics.contains(&atom.predicate),
+            Literal::Eq(..) => true,
+        }
+    }
+
+    fn ground_action(
+        &mut self,
+        action: &ActionDef,
+        operators: &mut Vec<Operator>,
+    ) -> Result<(), String> {
+        let parameters = &action.parameters;
+
+        let mut candidates = vec![];
+        for (name, kind) in parameters {
+            if !self.is_type(kind) {
+                return Err(format!("parameter {} has unknown type {}", name, kind));
+            }
+            let objects: Vec<usize> = (0..self.objects.len())
+                .filter(|&i| self.is_subtype(&self.objects[i].1, kind))
+                .collect();
+            candidates.push(objects);
+        }
+
+        // Static preconditions, each checked once its last parameter is bound
+        let mut statics: Vec<(&Literal, Option<usize>)> = vec![];
+        for literal in action.precondition.iter().filter(|l| self.is_static(l)) {
+            let last = literal
+                .args()
+                .iter()
+                .filter_map(|term| parameters.iter().position(|(name, _)| name == *term))
+                .max();
+            statics.push((literal, last));
+        }
+
+        for (literal, _) in statics.iter().filter(|(_, last)| last.is_none()) {
+            if !self.static_holds(literal, parameters, &[])? {
+                return Ok(());
+            }
+        }
+
+        let mut args: Vec<usize> = vec![];
+        let mut next = vec![0];
+
+        loop {
+            let depth = args.len();
+
+            if depth == parameters.len() {
+                operators.push(self.operator(action, &args)?);
+            } else if next[depth] < candidates[depth].len() {
+                args.push(candidates[depth][next[depth]]);
+                next[depth] += 1;
+
+                let mut consistent = true;
+                for (literal, _) in statics.iter().filter(|(_, last)| *last == Some(depth)) {
+                    consistent = consistent && self.static_holds(literal, parameters, &args)?;
+                }
+
+                if consistent {
+                    next.push(0);
+                } else {
+                    args.pop();
+                }
+                continue;
+            }
+
+            if args.pop().is_none() {
+                return Ok(());
+            }
+            next.pop();
+        }
+    }
+
+    fn operator(&mut self, action: &ActionDef, args: &[usize]) -> Result<Operator, String> {
+        let parameters = &action.parameters;
+
+        let mut name = format!("({}", action.name);
+        for &arg in args {
+            name.push(' ');
+            name.push_str(&self.objects[arg].0);
+        }
+        name.push(')');
+
+        let mut operator = Operator {
+            name,
+            pre: vec![],
+            pre_neg: vec![],
+            add: vec![],
+            del: vec![],
+            cost: if self.uses_costs { 0.0 } else { 1.0 },
+        };
+
+        let fluents: Vec<&Literal> = action
+            .precondition
+            .iter()
+            .filter(|l| !self.is_static(l))
+            .collect();
+
+        for literal in fluents {
+            match literal {
+                Literal::Pos(atom) => {
+                    let atom = self.ground_atom(atom, parameters, args)?;
+                    operator.pre.push(self.facts.id(atom));
+                }
+                Literal::Neg(atom) => {
+                    let atom = self.ground_atom(atom, parameters, args)?;
+                    operator.pre_neg.push(self.facts.id(atom));
+                }
+                Literal::Eq(..) => {}
+            }
+        }
+
+        for effect in &action.effect {
+            match effect {
+                Effect::Add(atom) => {
+                    let atom = self.ground_atom(atom, parameters, args)?;
+                    operator.add.push(self.facts.id(atom));
+                }
+                Effect::Del(atom) => {
+                    let atom = self.ground_atom(atom, parameters, args)?;
+                    operator.del.push(self.facts.id(atom));
+                }
+                Effect::Increase(CostExpr::Constant(cost)) => operator.cost += cost,
+                Effect::Increase(CostExpr::Function(function)) => {
+                    let function = self.ground_atom(function, parameters, args)?;
+                    let cost = self.problem.numbers.get(&function).ok_or_else(|| {
+                        format!(
+                            "no value for ({} {})",
+                            function.predicate,
+                            function.args.join(" ")
+                        )
+                    })?;
+                    operator.cost += cost;
+                }
+            }
+        }
+
+        if operator.cost.is_nan() || operator.cost < 0.0 {
+            return Err(format!("{} has a negative cost", operator.name));
+        }
+
+        Ok(operator)
+    }
+}
+
+/// Parse a PDDL domain and problem and ground them into a `Task`
+pub fn parse(domain_src: &str, problem_src: &str) -> Result<Task, PlanError> {
+    let domain = parse_domain(domain_src).map_err(|reason| invalid("domain", reason))?;
+    let problem = parse_problem(problem_src).map_err(|reason| invalid("problem", reason))?;
+    ground(&domain, &problem).map_err(|reason| invalid("problem", reason))
+}
+
+fn invalid(file: &str, reason: String) -> PlanError {
+    PlanError::InvalidScenario(format!("{}: {}", file, reason))
+}
+
+fn ground(domain: &DomainDef, problem: &ProblemDef) -> Result<Task, String> {
+    if problem.domain != domain.name {
+        return Err(format!(
+            "problem is for domain {} but the domain is {}",
+            problem.domain, domain.name
+        ));
+    }
+
+    let mut statics: HashSet<String> = HashSet::new();
+    let mut changed: HashSet<&str> = HashSet::new();
+    let mut uses_costs = false;
+
+    for action in &domain.actions {
+        for effect in &action.effect {
+            match effect {
+                Effect::Add(atom) | Effect::Del(atom) => {
+                    changed.insert(&atom.predicate);
+                }
+                Effect::Increase(_) => uses_costs = true,
+            }
+        }
+    }
+
+    for action in &domain.actions {
+        for literal in &action.precondition {
+            if let Literal::Pos(atom) | Literal::Neg(atom) = literal {
+                if !changed.contains(atom.predicate.as_str()) {
+                    statics.insert(atom.predicate.clone());
+                }
+            }
+        }
+    }
+
+    let mut grounder = Grounder {
+        domain,
+        problem,
+        objects: domain
+            .constants
+            .iter()
+            .chain(&problem.objects)
+            .cloned()
+            .collect(),
+        statics,
+        uses_costs,
+        facts: Facts::default(),
+    };
+    grounder.check_objects()?;
+
+    let mut operators = vec![];
+    for action in &domain.actions {
+        grounder.ground_action(action, &mut operators)?;
+    }
+
+    let mut goal = vec![];
+    let mut goal_neg = vec![];
+    for literal in &problem.goal {
+        match literal {
+            Literal::Pos(atom) => {
+                let atom = grounder.ground_atom(atom, &[], &[])?;
+                goal.push(grounder.facts.id(atom));
+            }
+            Literal::Neg(atom) => {
+                let atom = grounder.ground_atom(atom, &[], &[])?;
+                goal_neg.push(grounder.facts.id(atom));
+            }
+            Literal::Eq(..) => {
+                if !grounder.static_holds(literal, &[], &[])? {
+                    // An impossible goal: a fact nothing can make true
+                    let never = Atom {
+                        predicate: "=".to_owned(),
+                        args: literal.args().into_iter().cloned().collect(),
+                    };
+                    goal.push(grounder.facts.id(never));
+                }
+            }
+        }
+    }
+
+    // Only facts some operator or the goal mentions are kept
+    let facts = grounder.facts;
+    let mut init = FactSet::new(facts.names.len());
+    for atom in &problem.init {
+        if let Some(&fact) = facts.ids.get(atom) {
+            init.insert(fact);
+        }
+    }
+
+    Ok(Task {
+        facts: facts.names,
+        operators,
+        init,
+        goal,
+        goal_neg,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_pddl, Heuristic, SearchLimits};
+
+    const ROADS: &str = "
+        (define (domain roads)
+          (:requirements :typing :negative-preconditions :equality :action-costs)
+          (:types place vehicle - object car - vehicle)
+          (:predicates (at ?v - vehicle ?p - place) (road ?from ?to - place))
+          (:functions (total-cost) (length ?from ?to - place))
+          (:action drive
+            :parameters (?v - vehicle ?from ?to - place)
+            :precondition (and (at ?v ?from) (road ?from ?to) (not (= ?from ?to)))
+            :effect (and (at ?v ?to) (not (at ?v ?from))
+                         (increase (total-cost) (length ?from ?to)))))";
+
+    fn roads_problem(goal: &str) -> String {
+        format!(
+            "(define (problem trip) (:domain roads)
+               (:objects a b c - place c1 - car)
+               (:init (at c1 a) (road a b) (road b c) (road a c) (road a a)
+                      (= (length a b) 2) (= (length b c) 3) (= (length a c) 10)
+                      (= (length a a) 0))
+               (:goal {}))",
+            goal
+        )
+    }
+
+    fn operator_names(task: &Task) -> Vec<&str> {
+        let mut names: Vec<&str> = task.operators.iter().map(|op| op.name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn error(domain: &str, problem: &str) -> String {
+        match parse(domain, problem) {
+            Err(PlanError::InvalidScenario(reason)) => reason,
+            other => panic!("expected an invalid scenario, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn gripper_plans_are_optimal() {
+        let domain = include_str!("../pddl/gripper-domain.pddl");
+        let problem = include_str!("../pddl/gripper-problem.pddl");
+
+        for heuristic in [Heuristic::GoalCount, Heuristic::HMax] {
+            let result = run_pddl(domain, problem, heuristic, &SearchLimits::default());
+            assert!(result.success);
+            assert_eq!(result.total_cost, 17.0);
+            assert_eq!(result.suboptimality_bound, Some(1.0));
+        }
+    }
+
+    #[test]
+    fn static_preconditions_prune_ground_actions() {
+        let task = parse(ROADS, &roads_problem("(at c1 c)")).unwrap();
+
+        // Only declared roads are driven, `=` rules out the loop at a, and
+        // the static road facts are left out of the task
+        assert_eq!(
+            operator_names(&task),
+            ["(drive c1 a b)", "(drive c1 a c)", "(drive c1 b c)"]
+        );
+        assert!(task.facts.iter().all(|fact| !fact.starts_with("(road")));
+    }
+
+    #[test]
+    fn action_costs_come_from_the_problem() {
+        let task = parse(ROADS, &roads_problem("(at c1 c)")).unwrap();
+        let costs: Vec<Cost> = task.operators.iter().map(|op| op.cost).collect();
+        assert_eq!(costs, [2.0, 10.0, 3.0]);
+
+        let problem = roads_problem("(at c1 c)");
+        let result = run_pddl(ROADS, &problem, Heuristic::HMax, &SearchLimits::default());
+        assert!(result.success);
+        assert_eq!(result.total_cost, 5.0);
+    }
+
+    #[test]
+    fn actions_cost_one_without_action_costs() {
+        let domain = "
+            (define (domain lights)
+              (:action on :parameters (?l) :precondition (not (lit ?l)) :effect (lit ?l)))";
+        let problem = "
+            (define (problem room) (:domain lights)
+              (:objects l1 l2) (:init (lit l2)) (:goal (and (lit l1) (lit l2))))";
+
+        let task = parse(domain, problem).unwrap();
+        assert!(task.operators.iter().all(|op| op.cost == 1.0));
+
+        let result = run_pddl(
+            domain,
+            problem,
+            Heuristic::GoalCount,
+            &SearchLimits::default(),
+        );
+        assert!(result.success);
+        assert_eq!(result.total_cost, 1.0);
+    }
+
+    #[test]
+    fn objects_bind_to_parameters_of_their_supertypes() {
+        let problem = "
+            (define (problem trip) (:domain roads)
+              (:objects a b - place c1 - car v1 - vehicle x)
+              (:init (road a b) (= (length a b) 1))
+              (:goal (at v1 b)))";
+
+        let task = parse(ROADS, problem).unwrap();
+        assert_eq!(operator_names(&task), ["(drive c1 a b)", "(drive v1 a b)"]);
+    }
+
+    #[test]
+    fn negative_preconditions_and_goals() {
+        let domain = "
+            (define (domain lights)
+              (:action on :parameters (?l) :precondition (not (lit ?l)) :effect (lit ?l))
+              (:action off :parameters (?l) :precondition (lit ?l) :effect (not (lit ?l))))";
+        let problem = "
+            (define (problem room) (:domain lights)
+              (:objects l1 l2) (:init (lit l1)) (:goal (and (not (lit l1)) (lit l2))))";
+
+        let task = parse(domain, problem).unwrap();
+        let on = task
+            .operators
+            .iter()
+            .find(|op| op.name == "(on l1)")
+            .unwrap();
+        assert_eq!(on.pre_neg, on.add);
+        assert_eq!(task.goal_neg.len(), 1);
+
+        let result = run_pddl(domain, problem, Heuristic::HMax, &SearchLimits::default());
+        assert!(result.success);
+        assert_eq!(result.total_cost, 2.0);
+    }
+
+    #[test]
+    fn an_unequal_goal_equality_is_unreachable() {
+        let problem = roads_problem("(and (at c1 c) (= a b))");
+        let result = run_pddl(ROADS, &problem, Heuristic::HMax, &SearchLimits::default());
+        assert!(!result.success);
+        assert!(matches!(
+            result.failure_reason,
+            Some(PlanError::Unreachable)
+        ));
+
+        let problem = roads_problem("(and (at c1 c) (not (= a b)))");
+        let result = run_pddl(ROADS, &problem, Heuristic::HMax, &SearchLimits::default());
+        assert!(result.success);
+    }
+
+    #[test]
+    fn errors_name_the_file_and_the_problem() {
+        let problem = roads_problem("(at c1 c)");
+        let cases = [
+            (
+                "(define (domain roads)",
+                problem.as_str(),
+                "domain: unbalanced '('",
+            ),
+            (
+                "(define (domain roads) (:derived (p)))",
+                problem.as_str(),
+                "domain: unsupported domain section",
+            ),
+            (
+                "(define (domain roads) (:action go :precondition (or (p) (q)) :effect (p)))",
+                problem.as_str(),
+                "domain: action go: or is not supported here",
+            ),
+            (
+                ROADS,
+                "(define (problem trip) (:domain cities) (:goal (at c1 c)))",
+                "problem: problem is for domain cities but the domain is roads",
+            ),
+            (
+                ROADS,
+                "(define (problem trip) (:domain roads) (:objects a - town) (:goal (at a a)))",
+                "problem: object a has unknown type town",
+            ),
+            (
+                ROADS,
+                "(define (problem trip) (:domain roads) (:objects a a - place) (:goal (at a a)))",
+                "problem: object a is declared twice",
+            ),
+            (
+                ROADS,
+                "(define (problem trip) (:domain roads) (:objects a - place) (:goal (at z a)))",
+                "problem: unknown object z",
+            ),
+            (
+                ROADS,
+                "(define (problem trip) (:domain roads) (:objects a b - place c1 - car)
+                   (:init (road a b)) (:goal (at c1 b)))",
+                "problem: no value for (length a b)",
+            ),
+            (
+                ROADS,
+                "(define (problem trip) (:domain roads) (:objects a b - place c1 - car)
+                   (:init (road a b) (= (length a b) -1)) (:goal (at c1 b)))",
+                "problem: (drive c1 a b) has a negative cost",
+            ),
+        ];
+
+        for (domain, problem, expected) in cases {
+            let reason = error(domain, problem);
+            assert!(reason.starts_with(expected), "{:?}", reason);
+        }
+    }
+}
@@ -0,0 +1,715 @@
+use crate::a_star::Cost;
+use crate::actions::DoorState;
+use crate::schema::{GroundDomain, Literal};
+use crate::strips::{FactId, FactSet, Task};
+use crate::{Heuristic, Objective, State, World};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// Heuristics from the delete relaxation: actions only ever add facts, so
+// once a fact is reached it stays true. How cheaply each goal fact can be
+// reached then estimates the real remaining cost.
+
+/// An action of the relaxed task
+#[derive(Debug, Clone)]
+pub struct RelaxedOp {
+    pub pre: Vec<FactId>,
+    pub add: Vec<FactId>,
+    pub cost: Cost,
+}
+
+/// What the relaxed task has to reach
+#[derive(Debug, Clone)]
+pub enum Goal {
+    Fact(FactId),
+    All(Vec<Goal>),
+    Any(Vec<Goal>),
+}
+
+impl Goal {
+    fn facts(&self, facts: &mut Vec<FactId>) {
+        match self {
+            Goal::Fact(fact) => facts.push(*fact),
+            Goal::All(goals) | Goal::Any(goals) => {
+                for goal in goals {
+                    goal.facts(facts);
+                }
+            }
+        }
+    }
+
+    // The goal's cost from the cost of each fact, with the parts of an All
+    // combined by `all` and an Any costing its cheapest part
+    fn cost(&self, fact_cost: &dyn Fn(FactId) -> Cost, all: fn(Cost, Cost) -> Cost) -> Cost {
+        match self {
+            Goal::Fact(fact) => fact_cost(*fact),
+            Goal::All(goals) => goals.iter().map(|g| g.cost(fact_cost, all)).fold(0.0, all),
+            Goal::Any(goals) => goals
+                .iter()
+                .map(|g| g.cost(fact_cost, all))
+                .fold(f32::INFINITY, f32::min),
+        }
+    }
+
+    // The facts of the cheapest way to meet the goal, by additive cost
+    fn cheapest(&self, cost: &[Cost], facts: &mut Vec<FactId>) {
+        match self {
+            Goal::Fact(fact) => facts.push(*fact),
+            Goal::All(goals) => {
+                for goal in goals {
+                    goal.cheapest(cost, facts);
+                }
+            }
+            Goal::Any(goals) => {
+                let additive = |g: &&Goal| g.cost(&|f| cost[f], |a, b| a + b);
+                if let Some(goal) = goals
+                    .iter()
+                    .min_by(|a, b| additive(a).total_cmp(&additive(b)))
+                {
+                    goal.cheapest(cost, facts);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelaxedTask {
+    fact_count: usize,
+    ops: Vec<RelaxedOp>,
+    goal: Goal,
+    // Every fact the goal mentions
+    goal_facts: Vec<FactId>,
+    // The operators each fact is a precondition of
+    pre_of: Vec<Vec<usize>>,
+}
+
+// A fact waiting to be settled, cheapest first
+#[derive(PartialEq)]
+struct Reached {
+    cost: Cost,
+    fact: FactId,
+}
+
+impl Eq for Reached {}
+
+impl Ord for Reached {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Reached {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl RelaxedTask {
+    pub fn new(fact_count: usize, ops: Vec<RelaxedOp>, goal: Goal) -> RelaxedTask {
+        let mut pre_of = vec![vec![]; fact_count];
+        for (i, op) in ops.iter().enumerate() {
+            for &fact in &op.pre {
+                pre_of[fact].push(i);
+            }
+        }
+
+        let mut goal_facts = vec![];
+        goal.facts(&mut goal_facts);
+        goal_facts.sort_unstable();
+        goal_facts.dedup();
+
+        RelaxedTask {
+            fact_count,
+            ops,
+            goal,
+            goal_facts,
+            pre_of,
+        }
+    }
+
+    /// The relaxation of a STRIPS task. Negative preconditions and goals are
+    /// dropped along with the deletes.
+    pub fn from_task(task: &Task) -> RelaxedTask {
+        let ops = task
+            .operators
+            .iter()
+            .map(|op| RelaxedOp {
+                pre: op.pre.clone(),
+                add: op.add.clone(),
+                cost: op.cost,
+            })
+            .collect();
+
+        let goal = Goal::All(task.goal.iter().map(|&fact| Goal::Fact(fact)).collect());
+        RelaxedTask::new(task.facts.len(), ops, goal)
+    }
+
+    // The cost of reaching every fact from the given ones, an operator
+    // costing its own cost plus the max (or sum) of its preconditions'. Also
+    // returns the operator that first reached each fact.
+    fn explore(&self, state: &[FactId], sum: bool) -> (Vec<Cost>, Vec<Option<usize>>) {
+        let mut cost = vec![f32::INFINITY; self.fact_count];
+        let mut supporter = vec![None; self.fact_count];
+        let mut settled = vec![false; self.fact_count];
+        let mut unmet: Vec<usize> = self.ops.iter().map(|op| op.pre.len()).collect();
+        let mut pre_cost = vec![0.0; self.ops.len()];
+        let mut queue = BinaryHeap::new();
+
+        for &fact in state {
+            cost[fact] = 0.0;
+            queue.push(Reached { cost: 0.0, fact });
+        }
+
+        let mut fire =
+            |op: usize, op_cost: Cost, cost: &mut Vec<Cost>, queue: &mut BinaryHeap<_>| {
+                let reached = op_cost + self.ops[op].cost;
+                for &fact in &self.ops[op].add {
+                    if reached < cost[fact] {
+                        cost[fact] = reached;
+                        supporter[fact] = Some(op);
+                        queue.push(Reached {
+                            cost: reached,
+                            fact,
+                        });
+                    }
+                }
+            };
+
+        for (op, relaxed_op) in self.ops.iter().enumerate() {
+            if relaxed_op.pre.is_empty() {
+                fire(op, 0.0, &mut cost, &mut queue);
+            }
+        }
+
+        let mut goals_left = self.goal_facts.len();
+
+        while let Some(Reached { cost: c, fact }) = queue.pop() {
+            if settled[fact] {
+                continue;
+            }
+            settled[fact] = true;
+
+            // Facts settle cheapest first, so nothing after the last goal
+            // fact can change the estimate
+            if self.goal_facts.binary_search(&fact).is_ok() {
+                goals_left -= 1;
+                if goals_left == 0 {
+                    break;
+                }
+            }
+
+            for &op in &self.pre_of[fact] {
+                unmet[op] -= 1;
+                pre_cost[op] = if sum {
+                    pre_cost[op] + c
+                } else {
+                    f32::max(pre_cost[op], c)
+                };
+
+                if unmet[op] == 0 {
+                    fire(op, pre_cost[op], &mut cost, &mut queue);
+                }
+            }
+        }
+
+        (cost, supporter)
+    }
+
+    /// The cost of the most expensive goal fact. Admissible.
+    pub fn h_max(&self, state: &[FactId]) -> Cost {
+        let (cost, _) = self.explore(state, false);
+        self.goal.cost(&|f| cost[f], f32::max)
+    }
+
+    /// The sum of the goal facts' costs, assuming nothing is shared
+    /// between them
+    pub fn h_add(&self, state: &[FactId]) -> Cost {
+        let (cost, _) = self.explore(state, true);
+        self.goal.cost(&|f| cost[f], |a, b| a + b)
+    }
+
+    /// The cost of FF's relaxed plan: the actions that reach the goal facts
+    /// and their preconditions, each counted once. Of the alternatives of
+    /// an Any goal, the one cheapest by h_add is planned for.
+    pub fn h_ff(&self, state: &[FactId]) -> Cost {
+        let (cost, supporter) = self.explore(state, true);
+        if self.goal.cost(&|f| cost[f], |a, b| a + b) == f32::INFINITY {
+            return f32::INFINITY;
+        }
+
+        let mut in_plan = vec![false; self.ops.len()];
+        let mut marked = vec![false; self.fact_count];
+        let mut open = vec![];
+        self.goal.cheapest(&cost, &mut open);
+        let mut total = 0.0;
+
+        while let Some(fact) = open.pop() {
+            if marked[fact] {
+                continue;
+            }
+            marked[fact] = true;
+
+            // Facts of the state itself have no supporter
+            if let Some(op) = supporter[fact] {
+                if !in_plan[op] {
+                    in_plan[op] = true;
+                    total += self.ops[op].cost;
+                    open.extend(&self.ops[op].pre);
+                }
+            }
+        }
+
+        total
+    }
+
+    /// The number of goal facts not in the state
+    pub fn goal_count(&self, state: &[FactId]) -> Cost {
+        let missing = |f| if state.contains(&f) { 0.0 } else { 1.0 };
+        self.goal.cost(&missing, |a, b| a + b)
+    }
+
+    pub fn estimate(&self, heuristic: Heuristic, state: &[FactId]) -> Cost {
+        match heuristic {
+            Heuristic::GoalCount => self.goal_count(state),
+            Heuristic::HMax => self.h_max(state),
+            Heuristic::HAdd => self.h_add(state),
+            Heuristic::RelaxedPlan => self.h_ff(state),
+        }
+    }
+}
+
+/// The facts of a STRIPS state, for `RelaxedTask::estimate`
+pub fn task_facts(state: &FactSet) -> Vec<FactId> {
+    state.iter().collect()
+}
+
+// Preconditions, effects and cost
+type LiteralAction = (Vec<Literal<usize>>, Vec<Literal<usize>>, Cost);
+
+/// The relaxation of a scenario, with facts of the form "variable has
+/// value" taken from the fluent `Literal`s. The actors are merged into one
+/// that is wherever any of them is and holds whatever any of them holds,
+/// which keeps HMax admissible.
+pub struct ScenarioRelaxation {
+    task: RelaxedTask,
+    facts: HashMap<Literal<usize>, FactId>,
+    key_count: usize,
+}
+
+impl ScenarioRelaxation {
+    /// Enumerate the ground built-in actions and the domain's schema
+    /// actions. `state` only supplies how many holdables, flags and so on
+    /// the scenario has.
+    pub fn new(
+        world: &World,
+        state: &State,
+        domain: &GroundDomain,
+        objectives: &[Objective],
+    ) -> ScenarioRelaxation {
+        let mut actions: Vec<LiteralAction> = vec![];
+        if domain.builtin_actions {
+            builtin_actions(world, state, &mut actions);
+        }
+        for action in &domain.actions {
+            actions.push((
+                action.preconditions.clone(),
+                action.effects.clone(),
+                action.cost,
+            ));
+        }
+
+        let mut facts = HashMap::new();
+        let mut id = |literal: Literal<usize>| -> FactId {
+            let next = facts.len();
+            *facts.entry(literal).or_insert(next)
+        };
+
+        let holdable_count = state.holdable_pos.len();
+        let mut ops = vec![];
+        for (pre, add, cost) in actions {
+            // Whatever the actor holds moves with it
+            if let Some(&Literal::ActorPos(to)) =
+                add.iter().find(|l| matches!(l, Literal::ActorPos(_)))
+            {
+                for holdable in 0..holdable_count {
+                    let mut pre = pre.clone();
+                    let mut add = add.clone();
+                    pre.push(Literal::Holding(Some(holdable)));
+                    add.push(Literal::HoldablePos(holdable, to));
+                    ops.push(RelaxedOp {
+                        pre: pre.into_iter().map(&mut id).collect(),
+                        add: add.into_iter().map(&mut id).collect(),
+                        cost,
+                    });
+                }
+            }
+
+            ops.push(RelaxedOp {
+                pre: pre.into_iter().map(&mut id).collect(),
+                add: add.into_iter().map(&mut id).collect(),
+                cost,
+            });
+        }
+
+        let goal = Goal::All(
+            objectives
+                .iter()
+                .map(|o| objective_goal(o, false, world, state, &mut id))
+                .collect(),
+        );
+
+        ScenarioRelaxation {
+            task: RelaxedTask::new(facts.len(), ops, goal),
+            facts,
+            key_count: world.key_pos.len(),
+        }
+    }
+
+    pub fn estimate(&self, heuristic: Heuristic, state: &State) -> Cost {
+        let facts: Vec<FactId> = state_literals(state, self.key_count)
+            .iter()
+            .filter_map(|literal| self.facts.get(literal).copied())
+            .collect();
+
+        self.task.estimate(heuristic, &facts)
+    }
+}
+
+// The objective as a goal over the relaxation's facts. Negations are
+// pushed down to the objectives on single variables, where they become the
+// variable's other values.
+fn objective_goal(
+    objective: &Objective,
+    negated: bool,
+    world: &World,
+    state: &State,
+    id: &mut dyn FnMut(Literal<usize>) -> FactId,
+) -> Goal {
+    match objective {
+        Objective::Not(obj) => objective_goal(obj, !negated, world, state, id),
+        Objective::All(objs) | Objective::Any(objs) => {
+            let goals = objs
+                .iter()
+                .map(|o| objective_goal(o, negated, world, state, &mut *id))
+                .collect();
+
+            // Negating an All gives an Any of the negations, and the reverse
+            if matches!(objective, Objective::All(_)) != negated {
+                Goal::All(goals)
+            } else {
+                Goal::Any(goals)
+            }
+        }
+        _ => {
+            let (meets, fails) = variable_values(objective, world, state);
+            let literals = if negated { fails } else { meets };
+            Goal::Any(literals.into_iter().map(|l| Goal::Fact(id(l))).collect())
+        }
+    }
+}
+
+const DOOR_STATES: [DoorState; 4] = [
+    DoorState::Open,
+    DoorState::Closed,
+    DoorState::Locked,
+    DoorState::Broken,
+];
+
+// Every value of the variable an objective is about, split into those that
+// meet the objective and those that don't
+fn variable_values(
+    objective: &Objective,
+    world: &World,
+    state: &State,
+) -> (Vec<Literal<usize>>, Vec<Literal<usize>>) {
+    let positions = 0..world.pos_move_groups.len();
+    let holdables = 0..state.holdable_pos.len();
+
+    let values: Vec<(Literal<usize>, bool)> = match objective {
+        Objective::ActorPos(_, pos) => positions
+            .map(|p| (Literal::ActorPos(p), p == *pos))
+            .collect(),
+        Objective::DoorState(door, s) => DOOR_STATES
+            .iter()
+            .map(|d| (Literal::DoorState(*door, d.clone()), d == s))
+            .collect(),
+        Objective::DoorStateIn(door, states) => DOOR_STATES
+            .iter()
+            .map(|d| (Literal::DoorState(*door, d.clone()), states.contains(d)))
+            .collect(),
+        Objective::HoldablePos(holdable, pos) => positions
+            .map(|p| (Literal::HoldablePos(*holdable, p), p == *pos))
+            .collect(),
+        Objective::Holding(_, holding) => std::iter::once(None)
+            .chain(holdables.map(Some))
+            .map(|h| (Literal::Holding(h), h == *holding))
+            .collect(),
+        Objective::Filled(holdable, b) => [true, false]
+            .iter()
+            .map(|v| (Literal::Filled(*holdable, *v), v == b))
+            .collect(),
+        Objective::Activated(activatable, b) => [true, false]
+            .iter()
+            .map(|v| (Literal::Activated(*activatable, *v), v == b))
+            .collect(),
+        Objective::Holds(_, key, b) => [true, false]
+            .iter()
+            .map(|v| (Literal::Holds(*key, *v), v == b))
+            .collect(),
+        Objective::Flag(flag, b) => [true, false]
+            .iter()
+            .map(|v| (Literal::Flag(*flag, *v), v == b))
+            .collect(),
+        Objective::Not(_) | Objective::Any(_) | Objective::All(_) => vec![],
+    };
+
+    let (meets, fails): (Vec<_>, Vec<_>) = values.into_iter().partition(|(_, meets)| *meets);
+    (
+        meets.into_iter().map(|(l, _)| l).collect(),
+        fails.into_iter().map(|(l, _)| l).collect(),
+    )
+}
+
+// Every variable of the state with its value
+fn state_literals(state: &State, key_count: usize) -> Vec<Literal<usize>> {
+    let mut literals = vec![];
+
+    for actor in &state.actors {
+        literals.push(Literal::ActorPos(actor.pos));
+        literals.push(Literal::Holding(actor.holding));
+
+        for key in 0..key_count {
+            literals.push(Literal::Holds(key, actor.holds(key)));
+        }
+    }
+
+    for (door, s) in state.door_states.iter().enumerate() {
+        literals.push(Literal::DoorState(door, s.clone()));
+    }
+    for (holdable, &pos) in state.holdable_pos.iter().enumerate() {
+        literals.push(Literal::HoldablePos(holdable, pos));
+        literals.push(Literal::Filled(holdable, state.holdable_filled[holdable]));
+    }
+    for (activatable, &on) in state.activated.iter().enumerate() {
+        literals.push(Literal::Activated(activatable, on));
+    }
+    for (flag, &on) in state.flags.iter().enumerate() {
+        literals.push(Literal::Flag(flag, on));
+    }
+
+    literals
+}
+
+// The built-in actions of `get_neighbors` as preconditions and effects
+fn builtin_actions(world: &World, state: &State, actions: &mut Vec<LiteralAction>) {
+    let pos_count = world.pos_move_groups.len();
+
+    for from in 0..pos_count {
+        for to in 0..pos_count {
+            if from != to && world.pos_move_groups[from] == world.pos_move_groups[to] {
+                actions.push((
+                    vec![Literal::ActorPos(from)],
+                    vec![Literal::ActorPos(to)],
+                    1.0,
+                ));
+            }
+        }
+    }
+
+    for door in 0..state.door_states.len() {
+        let (a, b) = (world.door_side_a[door], world.door_side_b[door]);
+
+        for (side, other) in [(a, b), (b, a)] {
+            let at = Literal::ActorPos(side);
+            let door_is = |s: DoorState| Literal::DoorState(door, s);
+
+            actions.push((
+                vec![at.clone(), door_is(DoorState::Closed)],
+                vec![door_is(DoorState::Open)],
+                1.0,
+            ));
+            actions.push((
+                vec![at.clone(), door_is(DoorState::Open)],
+                vec![door_is(DoorState::Closed)],
+                1.0,
+            ));
+
+            for passable in [DoorState::Open, DoorState::Broken] {
+                actions.push((
+                    vec![at.clone(), door_is(passable)],
+                    vec![Literal::ActorPos(other)],
+                    1.0,
+                ));
+            }
+
+            for intact in [DoorState::Closed, DoorState::Locked] {
+                let broken = vec![door_is(DoorState::Broken)];
+                actions.push((
+                    vec![at.clone(), door_is(intact)],
+                    broken,
+                    world.break_door_cost,
+                ));
+            }
+
+            for key in (0..world.key_door.len()).filter(|&k| world.key_door[k] == door) {
+                let held = Literal::Holds(key, true);
+                actions.push((
+                    vec![at.clone(), door_is(DoorState::Locked), held.clone()],
+                    vec![door_is(DoorState::Closed)],
+                    1.0,
+                ));
+                actions.push((
+                    vec![at.clone(), door_is(DoorState::Closed), held],
+                    vec![door_is(DoorState::Locked)],
+                    1.0,
+                ));
+            }
+        }
+    }
+
+    for (key, &pos) in world.key_pos.iter().enumerate() {
+        actions.push((
+            vec![Literal::ActorPos(pos), Literal::Holds(key, false)],
+            vec![Literal::Holds(key, true)],
+            1.0,
+        ));
+    }
+
+    for holdable in 0..state.holdable_pos.len() {
+        for pos in 0..pos_count {
+            actions.push((
+                vec![
+                    Literal::ActorPos(pos),
+                    Literal::HoldablePos(holdable, pos),
+                    Literal::Holding(None),
+                ],
+                vec![Literal::Holding(Some(holdable))],
+                1.0,
+            ));
+        }
+        actions.push((
+            vec![Literal::Holding(Some(holdable))],
+            vec![Literal::Holding(None)],
+            1.0,
+        ));
+    }
+
+    for (activatable, &pos) in world.activatable_pos.iter().enumerate() {
+        actions.push((
+            vec![
+                Literal::ActorPos(pos),
+                Literal::Activated(activatable, false),
+            ],
+            vec![Literal::Activated(activatable, true)],
+            1.0,
+        ));
+        actions.push((
+            vec![
+                Literal::ActorPos(pos),
+                Literal::Activated(activatable, true),
+            ],
+            vec![Literal::Activated(activatable, false)],
+            1.0,
+        ));
+
+        if world.activatable_fills[activatable] {
+            for holdable in 0..state.holdable_pos.len() {
+                actions.push((
+                    vec![
+                        Literal::Activated(activatable, true),
+                        Literal::HoldablePos(holdable, pos),
+                    ],
+                    vec![Literal::Filled(holdable, true)],
+                    1.0,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_scenario, Scenario, SearchMode};
+    use serde_json::json;
+
+    // A locked door with its key behind the actor, and a faucet in the
+    // second room to fill a pot with
+    fn locked_kitchen() -> Scenario {
+        serde_json::from_value(json!({
+            "world": {
+                "pos_move_groups": [1, 1, 1, 2, 2],
+                "door_side_a": [2],
+                "door_side_b": [3],
+                "key_pos": [0],
+                "key_door": [0],
+                "activatable_pos": [4],
+                "activatable_fills": [true],
+            },
+            "state": {
+                "actors": [{"pos": 1}],
+                "door_states": ["Locked"],
+                "holdable_pos": [1],
+                "holdable_filled": [false],
+                "activated": [false],
+            },
+            "objectives": [
+                {"Filled": [0, true]},
+                {"HoldablePos": [0, 0]},
+                {"DoorState": [0, "Locked"]},
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn scenarios() -> Vec<Scenario> {
+        let files = [
+            include_str!("../scenario.json"),
+            include_str!("../button.json"),
+            include_str!("../kitchen.json"),
+        ];
+        let mut scenarios: Vec<Scenario> = files
+            .iter()
+            .map(|json| serde_json::from_str(json).unwrap())
+            .collect();
+        scenarios.push(locked_kitchen());
+        scenarios
+    }
+
+    #[test]
+    fn h_max_never_overestimates() {
+        for mut scenario in scenarios() {
+            let domain = scenario
+                .domain
+                .ground(&scenario.world, &scenario.state, &scenario.limits)
+                .unwrap();
+            let relaxation = ScenarioRelaxation::new(
+                &scenario.world,
+                &scenario.state,
+                &domain,
+                &scenario.objectives,
+            );
+
+            let start = scenario.state.clone();
+            assert!(relaxation.estimate(Heuristic::HMax, &start) > 0.0);
+            scenario.mode = SearchMode::UniformCost;
+            scenario.include_states = true;
+            let result = run_scenario(scenario);
+            assert!(result.success);
+
+            // Every state along an optimal plan, with the cost left from it
+            let mut states = vec![(start, result.total_cost)];
+            for step in &result.actions {
+                let state = step.state.clone().unwrap();
+                states.push((state, result.total_cost - step.g));
+            }
+
+            for (state, left) in states {
+                let h_max = relaxation.estimate(Heuristic::HMax, &state);
+                assert!(h_max <= left, "h_max {} but {} left", h_max, left);
+                assert!(relaxation.estimate(Heuristic::HAdd, &state) >= h_max);
+            }
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use crate::a_star::{Cost, Neighbor, PlanError, SearchLimits};
+use crate::actions::{Action, DoorState};
+use crate::lpa_star::LpaStar;
+use crate::relaxed::ScenarioRelaxation;
+use crate::schema::{Domain, GroundDomain};
+use crate::validation::{validate, Diagnostic};
+use crate::{
+    check_scenario, heuristic, invalid_scenario, is_goal, planner_result, DoorId, Heuristic,
+    Objective, PlanProgress, PlannerResult, PosId, PosMoveGroupId, Scenario, State, World,
+};
+use serde::Deserialize;
+
+/// Something in the world that changed while a plan was being carried out
+#[derive(Debug, Clone, Deserialize)]
+pub enum WorldChange {
+    /// The door is jammed or locked from elsewhere, so no built-in action
+    /// can open, close or pass through it. Action schemas are unaffected.
+    DoorBlocked(DoorId),
+    /// A blocked door can be used again
+    DoorUnblocked(DoorId),
+    /// Someone else opened, closed, locked or broke the door. Door states
+    /// are part of the plan's state, so the plan is repaired from the
+    /// current start with the door in its new state.
+    DoorState { door: DoorId, state: DoorState },
+    /// The position now belongs to another move group, e.g. because a room
+    /// was split in two
+    MoveGroup { pos: PosId, group: PosMoveGroupId },
+}
+
+/// Plans a scenario, then repairs the plan as the world changes instead of
+/// planning again from scratch. Plans start from the scenario's state
+/// until `advance` moves the start; the scenario's algorithm and mode are
+/// not used.
+pub struct Replanner {
+    world: World,
+    objectives: Vec<Objective>,
+    heuristic: Heuristic,
+    // Built for every heuristic but GoalCount, and again when move groups
+    // change. Blocked doors only take actions away, so it is not rebuilt
+    // for them and stays admissible.
+    relaxation: Option<ScenarioRelaxation>,
+    limits: SearchLimits<PlanProgress>,
+    include_states: bool,
+    blocked: Vec<DoorId>,
+    domain: Domain,
+    grounded: GroundDomain,
+    start: State,
+    search: LpaStar<State, Action>,
+}
+
+fn neighbors(
+    state: &State,
+    world: &World,
+    domain: &GroundDomain,
+    blocked: &[DoorId],
+) -> Vec<Neighbor<State, Action>> {
+    let mut neighbors = domain.neighbors(state, world);
+    neighbors.retain(|n| n.action.door().is_none_or(|door| !blocked.contains(&door)));
+    neighbors
+}
+
+fn estimate(
+    state: &State,
+    objectives: &[Objective],
+    chosen: Heuristic,
+    relaxation: &Option<ScenarioRelaxation>,
+    unit: Cost,
+) -> f32 {
+    match relaxation {
+        Some(relaxation) => relaxation.estimate(chosen, state),
+        None => heuristic(state, objectives, None, unit),
+    }
+}
+
+impl Replanner {
+    pub fn new(mut scenario: Scenario) -> Result<Replanner, PlanError> {
+        check_scenario(&scenario)?;
+        for actor in &mut scenario.state.actors {
+            actor.inventory.sort_unstable();
+        }
+        let grounded =
+            scenario
+                .domain
+                .ground(&scenario.world, &scenario.state, &scenario.limits)?;
+
+        let relaxation = match scenario.heuristic {
+            Heuristic::GoalCount => None,
+            _ => Some(ScenarioRelaxation::new(
+                &scenario.world,
+                &scenario.state,
+                &grounded,
+                &scenario.objectives,
+            )),
+        };
+
+        Ok(Replanner {
+            search: LpaStar::new(&scenario.state),
+            start: scenario.state,
+            domain: scenario.domain,
+            grounded,
+            world: scenario.world,
+            objectives: scenario.objectives,
+            heuristic: scenario.heuristic,
+            relaxation,
+            limits: scenario.limits,
+            include_states: scenario.include_states,
+            blocked: vec![],
+        })
+    }
+
+    pub fn plan(&mut self) -> PlannerResult {
+        let world = &self.world;
+        let objectives = &self.objectives;
+        let grounded = &self.grounded;
+        let blocked = &self.blocked;
+        let (chosen, relaxation) = (self.heuristic, &self.relaxation);
+        let unit = grounded.unit_cost(world);
+
+        let outcome = self.search.plan(
+            &|s| is_goal(s, objectives),
+            &|s| estimate(s, objectives, chosen, relaxation, unit),
+            &|s| neighbors(s, world, grounded, blocked),
+            &self.limits,
+        );
+
+        // LPA* expands like A*, so its plans are optimal when the
+        // heuristic never overestimates
+        let bound = Some(1.0).filter(|_| chosen.is_admissible());
+        planner_result(outcome, bound, self.include_states)
+    }
+
+    /// Plan from `state` from now on, e.g. once the first actions of the
+    /// plan have been carried out. The search so far is kept, and the next
+    /// call to `plan` repairs it from the new start.
+    pub fn advance(&mut self, state: &State) -> Result<(), PlanError> {
+        let start = &self.start;
+        let same_shape = state.actors.len() == start.actors.len()
+            && state.door_states.len() == start.door_states.len()
+            && state.holdable_pos.len() == start.holdable_pos.len()
+            && state.activated.len() == start.activated.len()
+            && state.flags.len() == start.flags.len();
+        if !same_shape {
+            let reason = "the state has different actors or items than the scenario's".to_string();
+            return Err(PlanError::InvalidScenario(reason));
+        }
+
+        // Only the state's own problems, as the rest was checked before,
+        // and move groups may since have changed
+        let scenario = Scenario {
+            world: self.world.clone(),
+            state: state.clone(),
+            objectives: self.objectives.clone(),
+            algorithm: Default::default(),
+            mode: Default::default(),
+            coordination: Default::default(),
+            heuristic: self.heuristic,
+            limits: Default::default(),
+            include_states: false,
+            domain: self.domain.clone(),
+        };
+        let problems: Vec<Diagnostic> = validate(&scenario)
+            .into_iter()
+            .filter(|p| p.path.starts_with("state"))
+            .collect();
+        if !problems.is_empty() {
+            return Err(invalid_scenario(&problems));
+        }
+
+        let mut state = state.clone();
+        for actor in &mut state.actors {
+            actor.inventory.sort_unstable();
+        }
+
+        let objectives = &self.objectives;
+        let (chosen, relaxation) = (self.heuristic, &self.relaxation);
+        let unit = self.grounded.unit_cost(&self.world);
+
+        self.search
+            .move_start(&state, &|s| is_goal(s, objectives), &|s| {
+                estimate(s, objectives, chosen, relaxation, unit)
+            })?;
+        self.start = state;
+        Ok(())
+    }
+
+    /// Update the world and mark the explored states whose actions the
+    /// change affects. The next call to `plan` repairs the plan.
+    pub fn apply(&mut self, change: WorldChange) -> Result<(), PlanError> {
+        let door_count = self.world.door_side_a.len();
+        let pos_count = self.world.pos_move_groups.len();
+
+        if let WorldChange::DoorState { door, state } = change {
+            if door >= door_count {
+                let reason = format!("door {} does not exist", door);
+                return Err(PlanError::InvalidScenario(reason));
+            }
+
+            let mut start = self.start.clone();
+            start.door_states[door] = state;
+            return self.advance(&start);
+        }
+
+        // Positions where an actor's available actions have changed
+        let affected: Vec<PosId> = match change {
+            WorldChange::DoorBlocked(door) | WorldChange::DoorUnblocked(door) => {
+                if door >= door_count {
+                    let reason = format!("door {} does not exist", door);
+                    return Err(PlanError::InvalidScenario(reason));
+                }
+
+                self.blocked.retain(|&d| d != door);
+                if let WorldChange::DoorBlocked(_) = change {
+                    self.blocked.push(door);
+                }
+
+                // Only actors standing at the door can act on it
+                vec![self.world.door_side_a[door], self.world.door_side_b[door]]
+            }
+            WorldChange::DoorState { .. } => unreachable!("handled above"),
+            WorldChange::MoveGroup { pos, group } => {
+                if pos >= pos_count {
+                    let reason = format!("position {} does not exist", pos);
+                    return Err(PlanError::InvalidScenario(reason));
+                }
+
+                // Moves change for everyone in the group the position left
+                // and the group it joined
+                let old_group = self.world.pos_move_groups[pos];
+                self.world.pos_move_groups[pos] = group;
+
+                // Schemas may test move groups anywhere, so ground them
+                // again and treat every state as affected
+                if !self.domain.action_schemas.is_empty() {
+                    self.grounded = self.domain.ground(&self.world, &self.start, &self.limits)?;
+                }
+
+                // The search keeps the estimate it first made for each
+                // state, and a relaxation's estimates can change anywhere,
+                // so the search starts over
+                if self.relaxation.is_some() {
+                    self.relaxation = Some(ScenarioRelaxation::new(
+                        &self.world,
+                        &self.start,
+                        &self.grounded,
+                        &self.objectives,
+                    ));
+                    self.search = LpaStar::new(&self.start);
+                    return Ok(());
+                }
+
+                if !self.domain.action_schemas.is_empty() {
+                    (0..pos_count).collect()
+                } else {
+                    (0..pos_count)
+                        .filter(|&p| {
+                            let g = self.world.pos_move_groups[p];
+                            g == old_group || g == group
+                        })
+                        .collect()
+                }
+            }
+        };
+
+        let world = &self.world;
+        let objectives = &self.objectives;
+        let grounded = &self.grounded;
+        let blocked = &self.blocked;
+        let (chosen, relaxation) = (self.heuristic, &self.relaxation);
+        let unit = grounded.unit_cost(world);
+
+        self.search.update(
+            &|s| s.actors.iter().any(|a| affected.contains(&a.pos)),
+            &|s| is_goal(s, objectives),
+            &|s| estimate(s, objectives, chosen, relaxation, unit),
+            &|s| neighbors(s, world, grounded, blocked),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_scenario;
+    use serde_json::json;
+
+    // Three rooms in a row, and a back door from the first straight into
+    // the last. `doors` keeps that many of the doors, so leaving out the
+    // back door stands in for blocking it.
+    fn scenario(groups: &[usize], doors: usize, heuristic: &str) -> Scenario {
+        let door_side_a = [3, 7, 1];
+        let door_side_b = [4, 8, 9];
+
+        serde_json::from_value(json!({
+            "world": {
+                "pos_move_groups": groups,
+                "door_side_a": &door_side_a[..doors],
+                "door_side_b": &door_side_b[..doors],
+            },
+            "state": {
+                "actors": [{"pos": 0}],
+                "door_states": vec!["Closed"; doors],
+            },
+            "objectives": [{"ActorPos": [0, 9]}],
+            "heuristic": heuristic,
+        }))
+        .unwrap()
+    }
+
+    const GROUPS: [usize; 10] = [1, 1, 1, 1, 2, 2, 2, 2, 3, 3];
+
+    fn fresh_cost(scenario: Scenario) -> f32 {
+        let result = run_scenario(scenario);
+        assert!(result.success);
+        result.total_cost
+    }
+
+    fn replanned_cost(replanner: &mut Replanner) -> f32 {
+        let result = replanner.plan();
+        assert!(result.success);
+        assert_eq!(result.suboptimality_bound, Some(1.0));
+        result.total_cost
+    }
+
+    #[test]
+    fn blocking_a_door_matches_a_fresh_plan() {
+        for heuristic in ["GoalCount", "HMax"] {
+            let mut replanner = Replanner::new(scenario(&GROUPS, 3, heuristic)).unwrap();
+            let before = replanned_cost(&mut replanner);
+            assert_eq!(before, fresh_cost(scenario(&GROUPS, 3, heuristic)));
+
+            replanner.apply(WorldChange::DoorBlocked(2)).unwrap();
+            let after = replanned_cost(&mut replanner);
+            assert_eq!(after, fresh_cost(scenario(&GROUPS, 2, heuristic)));
+            assert!(after > before);
+        }
+    }
+
+    #[test]
+    fn unblocking_a_door_matches_a_fresh_plan() {
+        for heuristic in ["GoalCount", "HMax"] {
+            let mut replanner = Replanner::new(scenario(&GROUPS, 3, heuristic)).unwrap();
+            replanner.apply(WorldChange::DoorBlocked(2)).unwrap();
+            replanned_cost(&mut replanner);
+
+            replanner.apply(WorldChange::DoorUnblocked(2)).unwrap();
+            let cost = replanned_cost(&mut replanner);
+            assert_eq!(cost, fresh_cost(scenario(&GROUPS, 3, heuristic)));
+        }
+    }
+
+    #[test]
+    fn regrouping_matches_a_fresh_plan() {
+        // The last room's far position joins the first room
+        let mut groups = GROUPS;
+        groups[9] = 1;
+
+        for heuristic in ["GoalCount", "HMax"] {
+            let mut replanner = Replanner::new(scenario(&GROUPS, 2, heuristic)).unwrap();
+            let before = replanned_cost(&mut replanner);
+
+            let change = WorldChange::MoveGroup { pos: 9, group: 1 };
+            replanner.apply(change).unwrap();
+            let after = replanned_cost(&mut replanner);
+            assert_eq!(after, fresh_cost(scenario(&groups, 2, heuristic)));
+            assert!(after < before);
+        }
+    }
+
+    #[test]
+    fn advancing_matches_a_fresh_plan() {
+        for heuristic in ["GoalCount", "HMax"] {
+            let mut replanner = Replanner::new(scenario(&GROUPS, 3, heuristic)).unwrap();
+            replanned_cost(&mut replanner);
+
+            // Along the way, back to the start, then somewhere off the plan
+            for pos in [3, 4, 0, 5] {
+                let mut moved = scenario(&GROUPS, 3, heuristic);
+                moved.state.actors[0].pos = pos;
+
+                replanner.advance(&moved.state).unwrap();
+                let cost = replanned_cost(&mut replanner);
+                assert_eq!(cost, fresh_cost(moved), "from {}", pos);
+            }
+        }
+    }
+
+    #[test]
+    fn setting_a_door_state_matches_a_fresh_plan() {
+        let states = [DoorState::Open, DoorState::Locked, DoorState::Broken];
+
+        for heuristic in ["GoalCount", "HMax"] {
+            for state in &states {
+                let mut replanner = Replanner::new(scenario(&GROUPS, 3, heuristic)).unwrap();
+                replanned_cost(&mut replanner);
+
+                let change = WorldChange::DoorState {
+                    door: 2,
+                    state: state.clone(),
+                };
+                replanner.apply(change).unwrap();
+                let cost = replanned_cost(&mut replanner);
+
+                let mut changed = scenario(&GROUPS, 3, heuristic);
+                changed.state.door_states[2] = state.clone();
+                assert_eq!(cost, fresh_cost(changed), "{:?}", state);
+            }
+        }
+    }
+
+    #[test]
+    fn advancing_to_a_state_of_another_scenario_is_rejected() {
+        let mut replanner = Replanner::new(scenario(&GROUPS, 3, "GoalCount")).unwrap();
+
+        let mut fewer_doors = scenario(&GROUPS, 2, "GoalCount").state;
+        assert!(replanner.advance(&fewer_doors).is_err());
+
+        fewer_doors.door_states.push(DoorState::Closed);
+        fewer_doors.actors[0].pos = 10;
+        assert!(replanner.advance(&fewer_doors).is_err());
+    }
+
+    #[test]
+    fn no_bound_without_an_admissible_heuristic() {
+        let mut replanner = Replanner::new(scenario(&GROUPS, 3, "HAdd")).unwrap();
+        let result = replanner.plan();
+        assert!(result.success);
+        assert_eq!(result.suboptimality_bound, None);
+    }
+
+    #[test]
+    fn changes_to_missing_doors_and_positions_are_rejected() {
+        let mut replanner = Replanner::new(scenario(&GROUPS, 3, "GoalCount")).unwrap();
+        assert!(replanner.apply(WorldChange::DoorBlocked(3)).is_err());
+        let change = WorldChange::MoveGroup { pos: 10, group: 1 };
+        assert!(replanner.apply(change).is_err());
+        let change = WorldChange::DoorState {
+            door: 3,
+            state: DoorState::Open,
+        };
+        assert!(replanner.apply(change).is_err());
+    }
+}
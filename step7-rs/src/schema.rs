@@ -0,0 +1,674 @@
+use crate::a_star::{Budget, Cost, Neighbor, PlanError, SearchLimits, SearchStats};
+use crate::actions::{Action, DoorState};
+use crate::validation::{Diagnostic, ErrorCode};
+use crate::{actor_neighbors, invalid_scenario, ActorId, State, World};
+use serde::Deserialize;
+use std::time::Instant;
+
+/// An argument of a literal: a fixed id, or the name of one of the
+/// schema's parameters, e.g. `"?door"`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Term {
+    Id(usize),
+    Param(String),
+}
+
+/// What kind of id a parameter or literal argument stands for
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    Pos,
+    Door,
+    Key,
+    Holdable,
+    Activatable,
+    Flag,
+}
+
+impl ParamType {
+    // How many ids of this kind the scenario has
+    fn count(self, world: &World, state: &State) -> usize {
+        match self {
+            ParamType::Pos => world.pos_move_groups.len(),
+            ParamType::Door => state.door_states.len(),
+            ParamType::Key => world.key_pos.len(),
+            ParamType::Holdable => state.holdable_pos.len(),
+            ParamType::Activatable => world.activatable_pos.len(),
+            ParamType::Flag => state.flags.len(),
+        }
+    }
+}
+
+/// A statement about a state, used as a precondition or an effect. The
+/// fluent literals mirror `Objective`, with ActorPos, Holding and Holds
+/// about the actor doing the action; the static ones describe the world
+/// and may only appear in preconditions.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Literal<T = Term> {
+    ActorPos(T),
+    DoorState(T, DoorState),
+    HoldablePos(T, T),
+    Filled(T, bool),
+    Activated(T, bool),
+    Holding(Option<T>),
+    Holds(T, bool),
+    Flag(T, bool),
+    /// The door joins the two positions, in either order
+    DoorSides(T, T, T),
+    SameMoveGroup(T, T),
+    Distinct(T, T),
+}
+
+impl<T> Literal<T> {
+    // The literal's arguments with the kind of id each one must be. None
+    // accepts any kind.
+    fn args(&self) -> Vec<(&T, Option<ParamType>)> {
+        use ParamType::*;
+
+        match self {
+            Literal::ActorPos(pos) => vec![(pos, Some(Pos))],
+            Literal::DoorState(door, _) => vec![(door, Some(Door))],
+            Literal::HoldablePos(holdable, pos) => {
+                vec![(holdable, Some(Holdable)), (pos, Some(Pos))]
+            }
+            Literal::Filled(holdable, _) => vec![(holdable, Some(Holdable))],
+            Literal::Activated(activatable, _) => vec![(activatable, Some(Activatable))],
+            Literal::Holding(holdable) => holdable.iter().map(|h| (h, Some(Holdable))).collect(),
+            Literal::Holds(key, _) => vec![(key, Some(Key))],
+            Literal::Flag(flag, _) => vec![(flag, Some(Flag))],
+            Literal::DoorSides(door, a, b) => {
+                vec![(door, Some(Door)), (a, Some(Pos)), (b, Some(Pos))]
+            }
+            Literal::SameMoveGroup(a, b) => vec![(a, Some(Pos)), (b, Some(Pos))],
+            Literal::Distinct(a, b) => vec![(a, None), (b, None)],
+        }
+    }
+
+    fn is_static(&self) -> bool {
+        matches!(
+            self,
+            Literal::DoorSides(..) | Literal::SameMoveGroup(..) | Literal::Distinct(..)
+        )
+    }
+
+    fn map<U>(&self, f: &dyn Fn(&T) -> U) -> Literal<U> {
+        match self {
+            Literal::ActorPos(pos) => Literal::ActorPos(f(pos)),
+            Literal::DoorState(door, s) => Literal::DoorState(f(door), s.clone()),
+            Literal::HoldablePos(holdable, pos) => Literal::HoldablePos(f(holdable), f(pos)),
+            Literal::Filled(holdable, b) => Literal::Filled(f(holdable), *b),
+            Literal::Activated(activatable, b) => Literal::Activated(f(activatable), *b),
+            Literal::Holding(holdable) => Literal::Holding(holdable.as_ref().map(f)),
+            Literal::Holds(key, b) => Literal::Holds(f(key), *b),
+            Literal::Flag(flag, b) => Literal::Flag(f(flag), *b),
+            Literal::DoorSides(door, a, b) => Literal::DoorSides(f(door), f(a), f(b)),
+            Literal::SameMoveGroup(a, b) => Literal::SameMoveGroup(f(a), f(b)),
+            Literal::Distinct(a, b) => Literal::Distinct(f(a), f(b)),
+        }
+    }
+}
+
+impl Literal<usize> {
+    pub fn holds(&self, state: &State, world: &World, actor: ActorId) -> bool {
+        match self {
+            Literal::ActorPos(pos) => state.actors[actor].pos == *pos,
+            Literal::DoorState(door, s) => state.door_states[*door] == *s,
+            Literal::HoldablePos(holdable, pos) => state.holdable_pos[*holdable] == *pos,
+            Literal::Filled(holdable, b) => state.holdable_filled[*holdable] == *b,
+            Literal::Activated(activatable, b) => state.activated[*activatable] == *b,
+            Literal::Holding(holdable) => state.actors[actor].holding == *holdable,
+            Literal::Holds(key, b) => state.actors[actor].holds(*key) == *b,
+            Literal::Flag(flag, b) => state.flags[*flag] == *b,
+            Literal::DoorSides(door, a, b) => {
+                let sides = (world.door_side_a[*door], world.door_side_b[*door]);
+                sides == (*a, *b) || sides == (*b, *a)
+            }
+            Literal::SameMoveGroup(a, b) => world.pos_move_groups[*a] == world.pos_move_groups[*b],
+            Literal::Distinct(a, b) => a != b,
+        }
+    }
+
+    // Make the literal true. Static literals are rejected while grounding.
+    fn apply(&self, state: &mut State, actor: ActorId) {
+        match self {
+            Literal::ActorPos(pos) => state.move_actor_to(actor, *pos),
+            Literal::DoorState(door, s) => state.door_states[*door] = s.clone(),
+            Literal::HoldablePos(holdable, pos) => state.holdable_pos[*holdable] = *pos,
+            Literal::Filled(holdable, b) => state.holdable_filled[*holdable] = *b,
+            Literal::Activated(activatable, b) => state.activated[*activatable] = *b,
+            Literal::Holding(holdable) => state.actors[actor].holding = *holdable,
+            Literal::Holds(key, true) => state.actors[actor].pick_up(*key),
+            Literal::Holds(key, false) => state.actors[actor].inventory.retain(|k| k != key),
+            Literal::Flag(flag, b) => state.flags[*flag] = *b,
+            Literal::DoorSides(..) | Literal::SameMoveGroup(..) | Literal::Distinct(..) => {}
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub param_type: ParamType,
+}
+
+fn default_schema_cost() -> Cost {
+    1.0
+}
+
+/// An action written as data: for every binding of its parameters whose
+/// preconditions hold, it leads to the state where its effects hold.
+/// Bindings whose effects would take a holdable or key from whoever has it
+/// don't apply.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ActionSchema {
+    pub name: String,
+    #[serde(default)]
+    pub parameters: Vec<Parameter>,
+    #[serde(default)]
+    pub preconditions: Vec<Literal>,
+    pub effects: Vec<Literal>,
+    #[serde(default = "default_schema_cost")]
+    pub cost: Cost,
+}
+
+fn default_builtin_actions() -> bool {
+    true
+}
+
+/// The actions available to the planner
+#[derive(Deserialize, Debug, Clone)]
+pub struct Domain {
+    /// Offer the actions built into the planner (moving, doors, keys, ...)
+    /// alongside the schemas
+    #[serde(default = "default_builtin_actions")]
+    pub builtin_actions: bool,
+    #[serde(default)]
+    pub action_schemas: Vec<ActionSchema>,
+}
+
+impl Default for Domain {
+    fn default() -> Self {
+        Domain {
+            builtin_actions: true,
+            action_schemas: vec![],
+        }
+    }
+}
+
+/// An action schema with every parameter bound
+#[derive(Debug, Clone)]
+pub struct GroundAction {
+    pub name: String,
+    pub args: Vec<usize>,
+    /// Only the fluent preconditions; static ones were checked while
+    /// grounding
+    pub preconditions: Vec<Literal<usize>>,
+    pub effects: Vec<Literal<usize>>,
+    pub cost: Cost,
+}
+
+impl GroundAction {
+    pub fn apply(
+        &self,
+        state: &State,
+        world: &World,
+        actor: ActorId,
+    ) -> Option<Neighbor<State, Action>> {
+        if !self
+            .preconditions
+            .iter()
+            .all(|l| l.holds(state, world, actor))
+        {
+            return None;
+        }
+
+        let mut new_state = state.clone();
+        for effect in &self.effects {
+            effect.apply(&mut new_state, actor);
+        }
+
+        // Effects may move a holdable out of someone's hands, or hand over
+        // something another actor has, so they only apply when they don't
+        if !holding_is_consistent(&new_state) {
+            return None;
+        }
+
+        let action = Action::Schema {
+            actor,
+            name: self.name.clone(),
+            args: self.args.clone(),
+        };
+        Some(Neighbor::new(new_state, self.cost, action))
+    }
+}
+
+// Whether every held holdable is where its actor is, and no holdable or key
+// is held twice, as validate requires of a scenario's state
+fn holding_is_consistent(state: &State) -> bool {
+    state.actors.iter().enumerate().all(|(i, actor)| {
+        let others = &state.actors[..i];
+
+        let holding = actor.holding.is_none_or(|holdable| {
+            state.holdable_pos[holdable] == actor.pos
+                && !others.iter().any(|a| a.holding == Some(holdable))
+        });
+        let inventory = actor
+            .inventory
+            .iter()
+            .all(|&key| !others.iter().any(|a| a.holds(key)));
+
+        holding && inventory
+    })
+}
+
+/// A domain ready for searching one scenario
+#[derive(Debug, Clone)]
+pub struct GroundDomain {
+    pub builtin_actions: bool,
+    pub actions: Vec<GroundAction>,
+}
+
+impl GroundDomain {
+    /// The least any action costs for each thing it changes, at most 1.
+    /// Each unmet objective takes at least this much to meet, even when
+    /// actions are cheap or one action meets several objectives.
+    pub fn unit_cost(&self, world: &World) -> Cost {
+        let builtin = if self.builtin_actions {
+            world.break_door_cost.min(1.0)
+        } else {
+            1.0
+        };
+
+        self.actions
+            .iter()
+            .map(|a| a.cost / a.effects.len().max(1) as Cost)
+            .fold(builtin, Cost::min)
+    }
+
+    pub fn neighbors(&self, state: &State, world: &World) -> Vec<Neighbor<State, Action>> {
+        (0..state.actors.len())
+            .flat_map(|actor| self.actor_neighbors(state, world, actor))
+            .collect()
+    }
+
+    /// The actions one actor can take
+    pub fn actor_neighbors(
+        &self,
+        state: &State,
+        world: &World,
+        actor: ActorId,
+    ) -> Vec<Neighbor<State, Action>> {
+        let mut neighbors = if self.builtin_actions {
+            actor_neighbors(state, world, actor)
+        } else {
+            vec![]
+        };
+
+        neighbors.extend(
+            self.actions
+                .iter()
+                .filter_map(|a| a.apply(state, world, actor)),
+        );
+        neighbors
+    }
+}
+
+// Most ground actions a domain may have. Every one of them is checked
+// whenever a state is expanded.
+const MAX_GROUND_ACTIONS: usize = 100_000;
+
+// Bindings tried between checks of the limits while grounding
+const BINDING_CHECK_INTERVAL: usize = 1024;
+
+/// Why a domain was not grounded
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroundError {
+    /// Action schemas that cannot be grounded, with where each problem is
+    Invalid(Vec<Diagnostic>),
+    /// Grounding ran into the limits or was cancelled
+    Stopped(PlanError),
+}
+
+impl From<GroundError> for PlanError {
+    fn from(error: GroundError) -> PlanError {
+        match error {
+            GroundError::Invalid(problems) => invalid_scenario(&problems),
+            GroundError::Stopped(error) => error,
+        }
+    }
+}
+
+impl Domain {
+    /// Every problem that would stop the schemas from being grounded, with
+    /// where each one is. Empty if there are none. The scenario must
+    /// already be valid.
+    pub fn check(&self, world: &World, state: &State) -> Vec<Diagnostic> {
+        let mut problems = vec![];
+        for (i, schema) in self.action_schemas.iter().enumerate() {
+            let path = format!("domain.action_schemas[{}]", i);
+            check_schema(schema, &path, world, state, &mut problems);
+        }
+        problems
+    }
+
+    /// Bind every schema's parameters to every combination of ids in the
+    /// scenario, keeping the bindings that satisfy the static preconditions.
+    /// Every schema is checked first, and none are grounded if any are
+    /// invalid. Gives up if there are too many, or if `limits` runs out of
+    /// time or is cancelled meanwhile.
+    pub fn ground<P>(
+        &self,
+        world: &World,
+        state: &State,
+        limits: &SearchLimits<P>,
+    ) -> Result<GroundDomain, GroundError> {
+        let started = Instant::now();
+        let mut problems = vec![];
+        let mut actions = vec![];
+
+        let checked: Vec<_> = self
+            .action_schemas
+            .iter()
+            .enumerate()
+            .map(|(i, schema)| {
+                let path = format!("domain.action_schemas[{}]", i);
+                check_schema(schema, &path, world, state, &mut problems)
+            })
+            .collect();
+        if !problems.is_empty() {
+            return Err(GroundError::Invalid(problems));
+        }
+
+        for (schema, statics) in self.action_schemas.iter().zip(&checked) {
+            ground_schema(schema, statics, world, state, limits, started, &mut actions)
+                .map_err(GroundError::Stopped)?;
+        }
+
+        Ok(GroundDomain {
+            builtin_actions: self.builtin_actions,
+            actions,
+        })
+    }
+}
+
+// Check the schema's cost, parameters and literals, adding what is wrong
+// with them to `problems`. `path` is the schema's own. Returns its static
+// preconditions, each with the last parameter it needs so they can prune
+// bindings early.
+fn check_schema<'a>(
+    schema: &'a ActionSchema,
+    path: &str,
+    world: &World,
+    state: &State,
+    problems: &mut Vec<Diagnostic>,
+) -> Vec<(&'a Literal, Option<usize>)> {
+    let mut problem = |code, path: String, message: String| {
+        problems.push(Diagnostic {
+            code,
+            message,
+            path,
+        })
+    };
+
+    if schema.cost.is_nan() || schema.cost < 0.0 {
+        problem(
+            ErrorCode::InvalidNumber,
+            format!("{}.cost", path),
+            format!("cost {} is not a non-negative number", schema.cost),
+        );
+    }
+
+    let params = &schema.parameters;
+    for (i, param) in params.iter().enumerate() {
+        if params[..i].iter().any(|p| p.name == param.name) {
+            problem(
+                ErrorCode::InvalidDomain,
+                format!("{}.parameters[{}].name", path, i),
+                format!("parameter {} is declared twice", param.name),
+            );
+        }
+    }
+
+    // Check every argument and note, for each literal, the last parameter
+    // it needs so static literals can prune bindings early
+    let preconditions = schema.preconditions.iter().enumerate();
+    let effects = schema.effects.iter().enumerate();
+    let literals = preconditions
+        .map(|(i, l)| (format!("{}.preconditions[{}]", path, i), l))
+        .chain(effects.map(|(i, l)| (format!("{}.effects[{}]", path, i), l)));
+    let mut last_param = vec![];
+    for (literal_path, literal) in literals {
+        let mut last = None;
+
+        for (term, expected) in literal.args() {
+            match term {
+                Term::Id(id) => {
+                    if let Some(kind) = expected {
+                        if *id >= kind.count(world, state) {
+                            problem(
+                                ErrorCode::UnknownId,
+                                literal_path.clone(),
+                                format!("{:?} {} does not exist", kind, id),
+                            );
+                        }
+                    }
+                }
+                Term::Param(name) => {
+                    let i = match params.iter().position(|p| p.name == *name) {
+                        Some(i) => i,
+                        None => {
+                            problem(
+                                ErrorCode::InvalidDomain,
+                                literal_path.clone(),
+                                format!("unknown parameter {}", name),
+                            );
+                            continue;
+                        }
+                    };
+
+                    if let Some(kind) = expected {
+                        if params[i].param_type != kind {
+                            problem(
+                                ErrorCode::InvalidDomain,
+                                literal_path.clone(),
+                                format!(
+                                    "parameter {} is a {:?} but is used as a {:?}",
+                                    name, params[i].param_type, kind
+                                ),
+                            );
+                        }
+                    }
+                    last = last.max(Some(i));
+                }
+            }
+        }
+
+        last_param.push(last);
+    }
+
+    for (i, literal) in schema.effects.iter().enumerate() {
+        if literal.is_static() {
+            problem(
+                ErrorCode::InvalidDomain,
+                format!("{}.effects[{}]", path, i),
+                format!("{:?} cannot be an effect", literal),
+            );
+        }
+    }
+
+    schema
+        .preconditions
+        .iter()
+        .zip(&last_param)
+        .filter(|(l, _)| l.is_static())
+        .map(|(l, last)| (l, *last))
+        .collect()
+}
+
+fn ground_schema<P>(
+    schema: &ActionSchema,
+    statics: &[(&Literal, Option<usize>)],
+    world: &World,
+    state: &State,
+    limits: &SearchLimits<P>,
+    started: Instant,
+    actions: &mut Vec<GroundAction>,
+) -> Result<(), PlanError> {
+    let params = &schema.parameters;
+    let counts: Vec<usize> = params
+        .iter()
+        .map(|p| p.param_type.count(world, state))
+        .collect();
+
+    let bind = |literal: &Literal, args: &[usize]| -> Literal<usize> {
+        literal.map(&|term| match term {
+            Term::Id(id) => *id,
+            Term::Param(name) => args[params.iter().position(|p| p.name == *name).unwrap()],
+        })
+    };
+
+    // Statics without parameters hold for every binding or for none. They
+    // say nothing about actors, so any actor will do.
+    let ground_statics_hold = statics
+        .iter()
+        .filter(|(_, last)| last.is_none())
+        .all(|(l, _)| bind(l, &[]).holds(state, world, 0));
+    if !ground_statics_hold {
+        return Ok(());
+    }
+
+    // Depth-first over the parameters, in declaration order
+    let mut args: Vec<usize> = vec![];
+    let mut next = vec![0];
+    let mut tried = 0;
+
+    loop {
+        let depth = args.len();
+
+        tried += 1;
+        if tried % BINDING_CHECK_INTERVAL == 0 {
+            if let Some(error) = limits.exceeded(started, &SearchStats::default(), 0) {
+                return Err(error);
+            }
+        }
+
+        if depth == params.len() {
+            if actions.len() == MAX_GROUND_ACTIONS {
+                return Err(PlanError::BudgetExceeded(Budget::GroundActions));
+            }
+
+            actions.push(GroundAction {
+                name: schema.name.clone(),
+                args: args.clone(),
+                preconditions: schema
+                    .preconditions
+                    .iter()
+                    .filter(|l| !l.is_static())
+                    .map(|l| bind(l, &args))
+                    .collect(),
+                effects: schema.effects.iter().map(|l| bind(l, &args)).collect(),
+                cost: schema.cost,
+            });
+        } else if next[depth] < counts[depth] {
+            args.push(next[depth]);
+            next[depth] += 1;
+
+            // Statics are checked as soon as their last parameter is bound
+            let consistent = statics
+                .iter()
+                .filter(|(_, last)| *last == Some(depth))
+                .all(|(l, _)| bind(l, &args).holds(state, world, 0));
+
+            if consistent {
+                next.push(0);
+            } else {
+                args.pop();
+            }
+            continue;
+        }
+
+        // Every value of this parameter has been tried
+        if args.pop().is_none() {
+            return Ok(());
+        }
+        next.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actions::Action;
+    use crate::{ActorId, Scenario, State};
+    use serde_json::json;
+
+    // Actor 0 has the pot and the key, and actor 1 stands next to them.
+    // Each schema would take one of them away from actor 0.
+    fn scenario() -> Scenario {
+        serde_json::from_value(json!({
+            "world": {
+                "pos_move_groups": [0, 0],
+                "door_side_a": [],
+                "door_side_b": [],
+                "key_pos": [0],
+                "key_door": [],
+            },
+            "state": {
+                "actors": [{"pos": 0, "holding": 0, "inventory": [0]}, {"pos": 0}],
+                "door_states": [],
+                "holdable_pos": [0],
+                "holdable_filled": [false],
+            },
+            "objectives": [],
+            "domain": {
+                "builtin_actions": false,
+                "action_schemas": [
+                    {"name": "Throw", "effects": [{"HoldablePos": [0, 1]}]},
+                    {"name": "Grab", "effects": [{"Holding": 0}]},
+                    {"name": "Pickpocket", "effects": [{"Holds": [0, true]}]},
+                ],
+            },
+        }))
+        .unwrap()
+    }
+
+    // The schemas an actor can apply
+    fn names(scenario: &Scenario, state: &State, actor: ActorId) -> Vec<String> {
+        let domain = scenario
+            .domain
+            .ground(&scenario.world, &scenario.state, &scenario.limits)
+            .unwrap();
+
+        domain
+            .actor_neighbors(state, &scenario.world, actor)
+            .into_iter()
+            .filter_map(|n| match n.action {
+                Action::Schema { name, .. } => Some(name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn effects_cannot_take_what_is_held() {
+        let scenario = scenario();
+
+        assert_eq!(names(&scenario, &scenario.state, 0), ["Grab", "Pickpocket"]);
+        assert_eq!(names(&scenario, &scenario.state, 1), Vec::<String>::new());
+    }
+
+    #[test]
+    fn effects_can_take_what_is_not_held() {
+        let scenario = scenario();
+        let mut state = scenario.state.clone();
+        state.actors[0].holding = None;
+        state.actors[0].inventory.clear();
+
+        let all = ["Throw", "Grab", "Pickpocket"];
+        assert_eq!(names(&scenario, &state, 1), all);
+
+        // Grabbing the pot where it isn't would leave it out of reach
+        state.holdable_pos[0] = 1;
+        assert_eq!(names(&scenario, &state, 1), ["Throw", "Pickpocket"]);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::a_star::{Cost, Neighbor, SearchOutcome, Step};
+use serde::Serialize;
+
+pub type FactId = usize;
+pub type OperatorId = usize;
+
+/// The facts true in a state, one bit per fact
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FactSet(Vec<u64>);
+
+impl FactSet {
+    pub fn new(fact_count: usize) -> FactSet {
+        FactSet(vec![0; fact_count.div_ceil(64)])
+    }
+
+    pub fn contains(&self, fact: FactId) -> bool {
+        self.0[fact / 64] & (1 << (fact % 64)) != 0
+    }
+
+    pub fn insert(&mut self, fact: FactId) {
+        self.0[fact / 64] |= 1 << (fact % 64);
+    }
+
+    pub fn remove(&mut self, fact: FactId) {
+        self.0[fact / 64] &= !(1 << (fact % 64));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = FactId> + '_ {
+        (0..self.0.len() * 64).filter(move |&fact| self.contains(fact))
+    }
+}
+
+// Serialized as the ids of the facts it holds
+impl Serialize for FactSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// A ground action. Deletes are applied before adds, so a fact that is
+/// both deleted and added ends up true.
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub name: String,
+    pub pre: Vec<FactId>,
+    /// Facts that must be false
+    pub pre_neg: Vec<FactId>,
+    pub add: Vec<FactId>,
+    pub del: Vec<FactId>,
+    pub cost: Cost,
+}
+
+impl Operator {
+    pub fn is_applicable(&self, state: &FactSet) -> bool {
+        self.pre.iter().all(|&f| state.contains(f))
+            && self.pre_neg.iter().all(|&f| !state.contains(f))
+    }
+
+    pub fn apply(&self, state: &FactSet) -> FactSet {
+        let mut new_state = state.clone();
+        for &f in &self.del {
+            new_state.remove(f);
+        }
+        for &f in &self.add {
+            new_state.insert(f);
+        }
+        new_state
+    }
+}
+
+/// A grounded planning problem over boolean facts
+#[derive(Debug, Clone)]
+pub struct Task {
+    /// A readable name for each fact, e.g. `(at ball1 rooma)`
+    pub facts: Vec<String>,
+    pub operators: Vec<Operator>,
+    pub init: FactSet,
+    pub goal: Vec<FactId>,
+    /// Facts that must be false in a goal state
+    pub goal_neg: Vec<FactId>,
+}
+
+impl Task {
+    pub fn is_goal(&self, state: &FactSet) -> bool {
+        self.goal.iter().all(|&f| state.contains(f))
+            && self.goal_neg.iter().all(|&f| !state.contains(f))
+    }
+
+    /// The number of goal facts that do not hold yet
+    pub fn goal_count(&self, state: &FactSet) -> f32 {
+        let missing = self.goal.iter().filter(|&&f| !state.contains(f)).count();
+        let extra = self.goal_neg.iter().filter(|&&f| state.contains(f)).count();
+        (missing + extra) as f32
+    }
+
+    /// The least any operator costs for each goal fact it can meet, at
+    /// most 1. The goal count times this never overestimates.
+    pub fn unit_cost(&self) -> Cost {
+        self.operators
+            .iter()
+            .filter_map(|op| {
+                let adds = op.add.iter().filter(|f| self.goal.contains(f)).count();
+                let dels = op.del.iter().filter(|f| self.goal_neg.contains(f)).count();
+                match adds + dels {
+                    0 => None,
+                    met => Some(op.cost / met as Cost),
+                }
+            })
+            .fold(1.0, Cost::min)
+    }
+
+    pub fn neighbors(&self, state: &FactSet) -> Vec<Neighbor<FactSet, OperatorId>> {
+        self.operators
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| op.is_applicable(state))
+            .map(|(i, op)| Neighbor::new(op.apply(state), op.cost, i))
+            .collect()
+    }
+
+    /// Replace the operator ids in a search's plans with their names
+    pub fn name_actions(
+        &self,
+        outcome: SearchOutcome<FactSet, OperatorId>,
+    ) -> SearchOutcome<FactSet, StripsAction> {
+        let name = |path: Vec<Step<FactSet, OperatorId>>| -> Vec<Step<FactSet, StripsAction>> {
+            path.into_iter()
+                .map(|step| Step {
+                    action: StripsAction {
+                        action: self.operators[step.action].name.clone(),
+                    },
+                    cost: step.cost,
+                    g: step.g,
+                    state: step.state,
+                })
+                .collect()
+        };
+
+        SearchOutcome {
+            result: outcome.result.map(name),
+            partial: outcome.partial.map(name),
+            stats: outcome.stats,
+        }
+    }
+}
+
+/// A step of a plan for a `Task`
+#[derive(Debug, Clone, Serialize)]
+pub struct StripsAction {
+    pub action: String,
+}
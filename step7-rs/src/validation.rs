@@ -0,0 +1,595 @@
+use crate::a_star::SearchMode;
+use crate::{Algorithm, Coordination, Objective, Scenario};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The kind of problem a diagnostic reports. Serialized in snake case,
+/// these are stable for clients to match on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The document is not JSON, or could not be read
+    MalformedJson,
+    /// The document is larger than the server accepts
+    PayloadTooLarge,
+    /// JSON that doesn't fit the format: a missing field, a wrong type, an
+    /// unknown variant
+    InvalidField,
+    /// Lists meant to have an entry for each door, key or item differ in
+    /// length
+    LengthMismatch,
+    /// An id of a position, door, key, item, actor or flag that doesn't
+    /// exist
+    UnknownId,
+    /// A cost or weight that is negative or NaN
+    InvalidNumber,
+    NoActors,
+    /// A door with both sides in the same move group
+    DoorWithinMoveGroup,
+    /// A key or holdable held by more than one actor, or twice by one
+    HeldTwice,
+    /// A held holdable that is not where its actor is
+    HeldOutOfReach,
+    /// Options that cannot be used together
+    IncompatibleOptions,
+    /// An action schema that cannot be grounded
+    InvalidDomain,
+    /// A planning job that doesn't exist, or finished long enough ago to
+    /// have been forgotten
+    UnknownJob,
+    /// The server is already running as many planning jobs as it allows
+    TooManyJobs,
+}
+
+/// A problem with a scenario and where it is, as a path into the
+/// scenario's JSON such as `state.actors[0].pos`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    pub path: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Deserialize JSON, reporting the path at which it stopped making sense.
+/// The path of the whole document is `.`.
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, Diagnostic> {
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+        let code = match error.inner().classify() {
+            Category::Data => ErrorCode::InvalidField,
+            Category::Io | Category::Syntax | Category::Eof => ErrorCode::MalformedJson,
+        };
+
+        Diagnostic {
+            code,
+            message: error.inner().to_string(),
+            path: error.path().to_string(),
+        }
+    })?;
+
+    deserializer.end().map_err(|error| Diagnostic {
+        code: ErrorCode::MalformedJson,
+        message: error.to_string(),
+        path: ".".to_string(),
+    })?;
+
+    Ok(value)
+}
+
+// The problems found so far
+#[derive(Default)]
+struct Report(Vec<Diagnostic>);
+
+impl Report {
+    fn add(&mut self, code: ErrorCode, path: String, message: String) {
+        self.0.push(Diagnostic {
+            code,
+            message,
+            path,
+        });
+    }
+
+    // Report an id that is not below the number of things it refers to
+    fn exists(&mut self, path: String, what: &str, id: usize, count: usize) {
+        if id >= count {
+            let message = format!("{} {} does not exist", what, id);
+            self.add(ErrorCode::UnknownId, path, message);
+        }
+    }
+}
+
+/// Every problem that would stop the scenario from being planned, such as
+/// references the action functions would otherwise index out of bounds.
+/// Empty if there are none.
+pub fn validate(scenario: &Scenario) -> Vec<Diagnostic> {
+    let world = &scenario.world;
+    let state = &scenario.state;
+    let pos_count = world.pos_move_groups.len();
+    let door_count = state.door_states.len();
+    let key_count = world.key_pos.len();
+    let activatable_count = world.activatable_pos.len();
+    let holdable_count = state.holdable_pos.len();
+    let actor_count = state.actors.len();
+
+    let mut report = Report::default();
+
+    // Lists that hold one entry for each door, key or item
+    let lengths = [
+        (
+            "world.door_side_a",
+            world.door_side_a.len(),
+            "state.door_states",
+            door_count,
+        ),
+        (
+            "world.door_side_b",
+            world.door_side_b.len(),
+            "state.door_states",
+            door_count,
+        ),
+        (
+            "world.key_door",
+            world.key_door.len(),
+            "world.key_pos",
+            key_count,
+        ),
+        (
+            "world.activatable_fills",
+            world.activatable_fills.len(),
+            "world.activatable_pos",
+            activatable_count,
+        ),
+        (
+            "state.activated",
+            state.activated.len(),
+            "world.activatable_pos",
+            activatable_count,
+        ),
+        (
+            "state.holdable_filled",
+            state.holdable_filled.len(),
+            "state.holdable_pos",
+            holdable_count,
+        ),
+    ];
+
+    for &(path, len, other, expected) in &lengths {
+        if len != expected {
+            report.add(
+                ErrorCode::LengthMismatch,
+                path.to_string(),
+                format!("has {} entries but {} has {}", len, other, expected),
+            );
+        }
+    }
+
+    let cost = world.break_door_cost;
+    if cost.is_nan() || cost < 0.0 {
+        report.add(
+            ErrorCode::InvalidNumber,
+            "world.break_door_cost".to_string(),
+            format!("{} is not a non-negative number", cost),
+        );
+    }
+
+    if let (Algorithm::Hierarchical, Coordination::Joint) =
+        (&scenario.algorithm, scenario.coordination)
+    {
+        report.add(
+            ErrorCode::IncompatibleOptions,
+            "coordination".to_string(),
+            "hierarchical plans cannot have joint steps".to_string(),
+        );
+    }
+
+    let mut weights = vec![];
+    if let SearchMode::Weighted(w) = scenario.mode {
+        weights.push(("mode.Weighted", w));
+    }
+    if let Algorithm::Anytime {
+        initial_weight,
+        weight_step,
+    } = scenario.algorithm
+    {
+        weights.push(("algorithm.Anytime.initial_weight", initial_weight));
+        weights.push(("algorithm.Anytime.weight_step", weight_step));
+    }
+
+    for (path, w) in weights {
+        if w.is_nan() || w < 0.0 {
+            report.add(
+                ErrorCode::InvalidNumber,
+                path.to_string(),
+                format!("search weight {} is not a non-negative number", w),
+            );
+        }
+    }
+
+    if state.actors.is_empty() {
+        report.add(
+            ErrorCode::NoActors,
+            "state.actors".to_string(),
+            "there are no actors".to_string(),
+        );
+    }
+
+    for (i, actor) in state.actors.iter().enumerate() {
+        let path = format!("state.actors[{}].pos", i);
+        report.exists(path, "position", actor.pos, pos_count);
+    }
+
+    for (door, (&a, &b)) in world.door_side_a.iter().zip(&world.door_side_b).enumerate() {
+        let path_a = format!("world.door_side_a[{}]", door);
+        let path_b = format!("world.door_side_b[{}]", door);
+
+        if a >= pos_count || b >= pos_count {
+            report.exists(path_a, "position", a, pos_count);
+            report.exists(path_b, "position", b, pos_count);
+            continue;
+        }
+
+        // Actors move freely within a move group, so such a door leads
+        // nowhere and is most likely a mistake in the world
+        let group = world.pos_move_groups[a];
+        if world.pos_move_groups[b] == group {
+            report.add(
+                ErrorCode::DoorWithinMoveGroup,
+                path_b,
+                format!(
+                    "door {} joins positions {} and {}, which are both in move group {}",
+                    door, a, b, group
+                ),
+            );
+        }
+    }
+
+    for (key, &pos) in world.key_pos.iter().enumerate() {
+        let path = format!("world.key_pos[{}]", key);
+        report.exists(path, "position", pos, pos_count);
+    }
+
+    for (key, &door) in world.key_door.iter().enumerate() {
+        let path = format!("world.key_door[{}]", key);
+        report.exists(path, "door", door, door_count);
+    }
+
+    for (activatable, &pos) in world.activatable_pos.iter().enumerate() {
+        let path = format!("world.activatable_pos[{}]", activatable);
+        report.exists(path, "position", pos, pos_count);
+    }
+
+    for (holdable, &pos) in state.holdable_pos.iter().enumerate() {
+        let path = format!("state.holdable_pos[{}]", holdable);
+        report.exists(path, "position", pos, pos_count);
+    }
+
+    for (i, actor) in state.actors.iter().enumerate() {
+        let others = &state.actors[..i];
+
+        if let Some(holdable) = actor.holding {
+            let path = format!("state.actors[{}].holding", i);
+
+            if holdable >= holdable_count {
+                report.exists(path, "holdable", holdable, holdable_count);
+            } else if state.holdable_pos[holdable] != actor.pos {
+                report.add(
+                    ErrorCode::HeldOutOfReach,
+                    path,
+                    format!("holdable {} is not at the actor's position", holdable),
+                );
+            } else if others.iter().any(|a| a.holding == Some(holdable)) {
+                report.add(
+                    ErrorCode::HeldTwice,
+                    path,
+                    format!("holdable {} is held more than once", holdable),
+                );
+            }
+        }
+
+        for (j, &key) in actor.inventory.iter().enumerate() {
+            let path = format!("state.actors[{}].inventory[{}]", i, j);
+
+            if key >= key_count {
+                report.exists(path, "key", key, key_count);
+            } else if actor.inventory[..j].contains(&key)
+                || others.iter().any(|a| a.inventory.contains(&key))
+            {
+                let message = format!("key {} is held more than once", key);
+                report.add(ErrorCode::HeldTwice, path, message);
+            }
+        }
+    }
+
+    // Objectives nested in Not, Any and All are checked too
+    let mut objectives: VecDeque<(String, &Objective)> = scenario
+        .objectives
+        .iter()
+        .enumerate()
+        .map(|(i, obj)| (format!("objectives[{}]", i), obj))
+        .collect();
+
+    while let Some((path, obj)) = objectives.pop_front() {
+        match obj {
+            Objective::ActorPos(actor, pos) => {
+                let path = format!("{}.ActorPos", path);
+                report.exists(format!("{}[0]", path), "actor", *actor, actor_count);
+                report.exists(format!("{}[1]", path), "position", *pos, pos_count);
+            }
+            Objective::DoorState(door, _) => {
+                let path = format!("{}.DoorState[0]", path);
+                report.exists(path, "door", *door, door_count);
+            }
+            Objective::DoorStateIn(door, _) => {
+                let path = format!("{}.DoorStateIn[0]", path);
+                report.exists(path, "door", *door, door_count);
+            }
+            Objective::HoldablePos(holdable, pos) => {
+                let path = format!("{}.HoldablePos", path);
+                report.exists(
+                    format!("{}[0]", path),
+                    "holdable",
+                    *holdable,
+                    holdable_count,
+                );
+                report.exists(format!("{}[1]", path), "position", *pos, pos_count);
+            }
+            Objective::Filled(holdable, _) => {
+                let path = format!("{}.Filled[0]", path);
+                report.exists(path, "holdable", *holdable, holdable_count);
+            }
+            Objective::Activated(activatable, _) => {
+                let path = format!("{}.Activated[0]", path);
+                report.exists(path, "activatable", *activatable, activatable_count);
+            }
+            Objective::Holding(actor, holdable) => {
+                let path = format!("{}.Holding", path);
+                report.exists(format!("{}[0]", path), "actor", *actor, actor_count);
+                if let Some(holdable) = holdable {
+                    let path = format!("{}[1]", path);
+                    report.exists(path, "holdable", *holdable, holdable_count);
+                }
+            }
+            Objective::Holds(actor, key, _) => {
+                let path = format!("{}.Holds", path);
+                report.exists(format!("{}[0]", path), "actor", *actor, actor_count);
+                report.exists(format!("{}[1]", path), "key", *key, key_count);
+            }
+            Objective::Flag(flag, _) => {
+                let path = format!("{}.Flag[0]", path);
+                report.exists(path, "flag", *flag, state.flags.len());
+            }
+            Objective::Not(inner) => objectives.push_back((format!("{}.Not", path), inner)),
+            Objective::Any(inner) => objectives.extend(
+                inner
+                    .iter()
+                    .enumerate()
+                    .map(|(i, obj)| (format!("{}.Any[{}]", path, i), obj)),
+            ),
+            Objective::All(inner) => objectives.extend(
+                inner
+                    .iter()
+                    .enumerate()
+                    .map(|(i, obj)| (format!("{}.All[{}]", path, i), obj)),
+            ),
+        }
+    }
+
+    report.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_json, validate, ErrorCode};
+    use crate::Scenario;
+    use serde_json::{json, Value};
+
+    // Two rooms joined by a door, with a key, a pot and a stove. The
+    // payload, job and server codes are made by the server and tested there.
+    fn scenario() -> Value {
+        json!({
+            "world": {
+                "pos_move_groups": [0, 1],
+                "door_side_a": [0],
+                "door_side_b": [1],
+                "key_pos": [0],
+                "key_door": [0],
+                "activatable_pos": [1],
+                "activatable_fills": [true],
+            },
+            "state": {
+                "actors": [{"pos": 0}, {"pos": 1}],
+                "door_states": ["Closed"],
+                "holdable_pos": [0],
+                "holdable_filled": [false],
+                "activated": [false],
+            },
+            "objectives": [{"ActorPos": [0, 1]}],
+        })
+    }
+
+    fn problems(scenario: Value) -> Vec<(ErrorCode, String)> {
+        let scenario: Scenario = serde_json::from_value(scenario).unwrap();
+        validate(&scenario)
+            .into_iter()
+            .map(|d| (d.code, d.path))
+            .collect()
+    }
+
+    fn only(code: ErrorCode, path: &str) -> Vec<(ErrorCode, String)> {
+        vec![(code, path.to_string())]
+    }
+
+    #[test]
+    fn a_valid_scenario_has_no_problems() {
+        assert_eq!(problems(scenario()), vec![]);
+    }
+
+    #[test]
+    fn unreadable_json_is_malformed() {
+        let json = scenario().to_string();
+
+        let error = from_json::<Scenario>(&json[..json.len() - 1]).unwrap_err();
+        assert_eq!(error.code, ErrorCode::MalformedJson);
+
+        let error = from_json::<Scenario>(&format!("{} {{", json)).unwrap_err();
+        assert_eq!(
+            (error.code, error.path.as_str()),
+            (ErrorCode::MalformedJson, ".")
+        );
+    }
+
+    #[test]
+    fn json_of_the_wrong_shape_is_an_invalid_field() {
+        let mut scenario = scenario();
+        scenario["state"]["actors"][1]["pos"] = json!("kitchen");
+
+        let error = from_json::<Scenario>(&scenario.to_string()).unwrap_err();
+        assert_eq!(
+            (error.code, error.path.as_str()),
+            (ErrorCode::InvalidField, "state.actors[1].pos")
+        );
+    }
+
+    #[test]
+    fn lists_of_different_lengths_are_a_length_mismatch() {
+        let mut scenario = scenario();
+        scenario["state"]["holdable_filled"] = json!([false, true]);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::LengthMismatch, "state.holdable_filled")
+        );
+    }
+
+    #[test]
+    fn missing_ids_are_unknown() {
+        let mut scenario = scenario();
+        scenario["world"]["key_door"] = json!([3]);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::UnknownId, "world.key_door[0]")
+        );
+    }
+
+    #[test]
+    fn negative_costs_and_weights_are_invalid_numbers() {
+        let mut scenario = scenario();
+        scenario["world"]["break_door_cost"] = json!(-1.0);
+        scenario["mode"] = json!({"Weighted": -2.0});
+
+        assert_eq!(
+            problems(scenario),
+            vec![
+                (
+                    ErrorCode::InvalidNumber,
+                    "world.break_door_cost".to_string()
+                ),
+                (ErrorCode::InvalidNumber, "mode.Weighted".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_scenario_needs_actors() {
+        let mut scenario = scenario();
+        scenario["state"]["actors"] = json!([]);
+        scenario["objectives"] = json!([]);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::NoActors, "state.actors")
+        );
+    }
+
+    #[test]
+    fn doors_join_different_move_groups() {
+        let mut scenario = scenario();
+        scenario["world"]["pos_move_groups"] = json!([0, 0]);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::DoorWithinMoveGroup, "world.door_side_b[0]")
+        );
+    }
+
+    #[test]
+    fn nothing_is_held_twice() {
+        let mut scenario = scenario();
+        scenario["state"]["actors"] = json!([{"pos": 0, "holding": 0}, {"pos": 0, "holding": 0}]);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::HeldTwice, "state.actors[1].holding")
+        );
+
+        let mut scenario = self::scenario();
+        scenario["state"]["actors"][1]["inventory"] = json!([0, 0]);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::HeldTwice, "state.actors[1].inventory[1]")
+        );
+    }
+
+    #[test]
+    fn held_holdables_are_with_their_actor() {
+        let mut scenario = scenario();
+        scenario["state"]["actors"][1]["holding"] = json!(0);
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::HeldOutOfReach, "state.actors[1].holding")
+        );
+    }
+
+    #[test]
+    fn hierarchical_plans_cannot_be_joint() {
+        let mut scenario = scenario();
+        scenario["algorithm"] = json!("Hierarchical");
+        scenario["coordination"] = json!("Joint");
+
+        assert_eq!(
+            problems(scenario),
+            only(ErrorCode::IncompatibleOptions, "coordination")
+        );
+    }
+
+    #[test]
+    fn schemas_that_cannot_be_grounded_are_an_invalid_domain() {
+        let mut scenario = scenario();
+        scenario["domain"] = json!({
+            "action_schemas": [{
+                "name": "Merge",
+                "preconditions": [{"ActorPos": 0}],
+                "effects": [{"Flag": [0, true]}, {"SameMoveGroup": [0, 1]}],
+            }],
+        });
+        scenario["state"]["flags"] = json!([false]);
+        let scenario: Scenario = serde_json::from_value(scenario).unwrap();
+
+        let problems: Vec<_> = scenario
+            .domain
+            .check(&scenario.world, &scenario.state)
+            .into_iter()
+            .map(|d| (d.code, d.path))
+            .collect();
+        assert_eq!(
+            problems,
+            only(
+                ErrorCode::InvalidDomain,
+                "domain.action_schemas[0].effects[1]"
+            )
+        );
+    }
+}